@@ -0,0 +1,28 @@
+; Languages nested in C, resolved through the highlighter configs
+;
+; There is no grammar for `printf` and `kernel-doc`, they are painted by
+; built-in fallbacks unless a grammar is loaded for them.
+
+; Inline assembly
+((gnu_asm_expression
+   assembly_code: [(string_literal) (concatenated_string)] @injection.content)
+ (#set! injection.language "asm"))
+
+; Format strings of the logging functions, as their first argument...
+((call_expression
+   function: (identifier) @_function
+   arguments: (argument_list . [(string_literal) (concatenated_string)] @injection.content))
+ (#match? @_function "^(printk|pr_[a-z_]+|panic)$")
+ (#set! injection.language "printf"))
+
+; ...or their second one, after the device or the condition
+((call_expression
+   function: (identifier) @_function
+   arguments: (argument_list . (_) . [(string_literal) (concatenated_string)] @injection.content))
+ (#match? @_function "^((dev|netdev)_[a-z_]+|seq_printf|WARN(_ONCE)?|snprintf|scnprintf|sprintf)$")
+ (#set! injection.language "printf"))
+
+; kernel-doc comments
+((comment) @injection.content
+ (#match? @injection.content "^/\\*\\*([^*/]|$)")
+ (#set! injection.language "kernel-doc"))
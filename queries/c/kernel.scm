@@ -0,0 +1,41 @@
+; Linux kernel idioms, layered on top of the C highlight query
+;
+; These patterns come last, so they take precedence over the generic C
+; patterns matching the same nodes (e.g. `@function` for any call).
+
+; Annotations for sections, address spaces, sparse and the compiler
+([(identifier) (type_identifier)] @attribute.kernel
+ (#match? @attribute.kernel "^(__init|__exit|__initdata|__initconst|__exitdata|__ref|__refdata|__meminit|__user|__kernel|__iomem|__percpu|__rcu|__force|__bitwise|__must_check|__maybe_unused|__always_unused|__always_inline|__noreturn|__cold|__hot|__pure|__weak|__packed|__aligned|__read_mostly|__ro_after_init|__cacheline_aligned|__cacheline_aligned_in_smp|__counted_by|__nonstring|__malloc|__printf|__scanf|__section|__visible|__used|__latent_entropy|__randomize_layout|__acquires|__releases|__must_hold|__cleanup|__free|noinline|notrace|noinstr|asmlinkage)$"))
+
+; Branch prediction hints
+(call_expression
+  function: (identifier) @keyword.hint
+  (#any-of? @keyword.hint "likely" "unlikely"))
+
+; Symbol exports
+(call_expression
+  function: (identifier) @function.export
+  (#match? @function.export "^EXPORT_(SYMBOL|PER_CPU_SYMBOL|TRACEPOINT_SYMBOL|STATIC_CALL)"))
+
+; Module boilerplate (`module_init`, `module_platform_driver`, `MODULE_LICENSE`)
+(call_expression
+  function: (identifier) @function.module
+  (#match? @function.module "^(module|MODULE)_"))
+
+; Iterator macros, which open a loop body like `for` does
+(call_expression
+  function: (identifier) @keyword.iterator
+  (#match? @keyword.iterator "(^|_)for_each(_|$)"))
+
+; Logging
+(call_expression
+  function: (identifier) @function.log
+  (#match? @function.log "^((pr|dev|netdev)_(emerg|alert|crit|err|warn|notice|info|dbg|debug|cont|devel)|printk)"))
+
+; Kconfig symbols, in `IS_ENABLED(CONFIG_FOO)` and `#ifdef CONFIG_FOO` alike
+(call_expression
+  function: (identifier) @function.config
+  (#any-of? @function.config "IS_ENABLED" "IS_BUILTIN" "IS_MODULE" "IS_REACHABLE"))
+
+((identifier) @constant.config
+ (#match? @constant.config "^CONFIG_[A-Z0-9_]+$"))
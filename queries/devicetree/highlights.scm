@@ -0,0 +1,24 @@
+; Devicetree highlights, layered on top of the stock query
+;
+; The stock query paints every identifier as `@variable` after the more
+; specific patterns, which would take precedence over them, and it has no
+; patterns for literals.
+
+(node
+  name: (identifier) @tag)
+
+(node
+  label: (identifier) @label)
+
+(property
+  label: (identifier) @label)
+
+(property
+  name: (identifier) @property)
+
+(string_literal) @string
+
+[
+  (integer_literal)
+  (byte_string_literal)
+] @number
@@ -0,0 +1,16 @@
+; Standard capture names for the Kconfig highlight query, layered on top of it
+;
+; The stock query uses older capture names the highlighter does not
+; recognize (`@conditional`, `@include`), these patterns take precedence
+; over them.
+
+"source" @keyword.import
+
+[
+  "if"
+  "endif"
+  "depends on"
+  "select"
+  "imply"
+  "visible if"
+] @keyword.conditional
@@ -0,0 +1,31 @@
+; Standard capture names for the make highlight query, layered on top of it
+;
+; The stock query uses older capture names the highlighter does not
+; recognize (`@conditional`, `@include`, ...), these patterns take
+; precedence over them.
+
+[
+  "ifeq"
+  "ifneq"
+  "ifdef"
+  "ifndef"
+  "else"
+  "endif"
+  "if"
+  "or"
+  "and"
+] @keyword.conditional
+
+"foreach" @keyword.repeat
+
+[
+  "include"
+  "sinclude"
+  "-include"
+] @keyword.import
+
+[
+  "error"
+  "warning"
+  "info"
+] @function.builtin
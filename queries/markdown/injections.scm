@@ -0,0 +1,17 @@
+; Languages nested in Markdown, from the stock injections of tree-sitter-md
+;
+; The inline content has hidden children in the block tree, so it must be
+; injected with them or nothing is left to highlight.
+
+(fenced_code_block
+  (info_string
+    (language) @injection.language)
+  (code_fence_content) @injection.content)
+
+((html_block) @injection.content (#set! injection.language "html"))
+
+([(minus_metadata) (plus_metadata)] @injection.content (#set! injection.language "yaml"))
+
+((inline) @injection.content
+ (#set! injection.language "markdown_inline")
+ (#set! injection.include-children))
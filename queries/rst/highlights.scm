@@ -0,0 +1,56 @@
+; reStructuredText highlights, as tree-sitter-rst does not ship any
+
+(title) @text.title
+
+[
+  "adornment"
+  (transition)
+  "bullet"
+  ".."
+] @punctuation.special
+
+(comment) @comment
+
+; Inline markup
+(emphasis) @text.emphasis
+(strong) @text.strong
+(attribution) @text.emphasis
+(term) @text.strong
+
+[
+  (literal)
+  (literal_block)
+  (doctest_block)
+] @text.literal
+
+(standalone_hyperlink) @text.uri
+
+[
+  (reference)
+  (footnote_reference)
+  (citation_reference)
+  (substitution_reference)
+  (interpreted_text)
+] @text.reference
+
+; Roles (`:c:func:`) and directives (`.. kernel-doc::`)
+(role) @function.macro
+
+(directive
+  name: (type) @function)
+
+(field_name) @property
+
+; Link targets, footnotes, citations, and substitutions
+(target
+  name: (name) @label)
+(target
+  link: (link) @text.uri)
+
+[
+  (footnote name: (label) @label)
+  (citation name: (label) @label)
+]
+
+(substitution_definition
+  name: (substitution) @constant)
@@ -0,0 +1,11 @@
+; Languages nested in Rust, on top of the stock injections
+
+; Doc comments are Markdown, one document across all their lines
+((line_comment
+   doc: (doc_comment) @injection.content)
+ (#set! injection.language "markdown")
+ (#set! injection.combined))
+
+((block_comment
+   doc: (doc_comment) @injection.content)
+ (#set! injection.language "markdown"))
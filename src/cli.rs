@@ -0,0 +1,96 @@
+use std::env;
+
+use crate::parser::quote::QuoteProfile;
+
+const USAGE: &str = "\
+Usage: kmailight [OPTIONS] [FILE]
+
+Syntax highlight a patch email (or a whole thread) read from FILE or stdin.
+Gzip compressed input (e.g. a `t.mbox.gz` from lore) is decompressed.
+
+Options:
+  -m, --mbox           Split the input into messages at the mbox `From ` lines
+                       (default for files ending with `.mbox` or `.mbox.gz`)
+  -q, --quote PROFILE  How quoted lines are recognized (default: lenient)
+                         strict     `>` marks only, as in RFC 3676 (`>> text`)
+                         lenient    `>` marks with optional spaces (`> > text`)
+                         pipe       `>` and `|` marks (`| text`)
+                         supercite  `>` marks and attributions (`Linus> text`)
+  -t, --theme THEME    Color theme: dark (default), light, solarized,
+                       high-contrast, a theme of the configuration, or a file
+  -h, --help           Print this help
+";
+
+/// Command line options
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Input file, stdin if `None`
+    pub path: Option<String>,
+    /// Treat the input as an mbox with many messages
+    pub mbox: bool,
+    /// How the quote prefixes of quoted lines are recognized
+    pub quote: QuoteProfile,
+    /// Color theme, the one of the configuration (or the default one) if `None`
+    pub theme: Option<String>,
+}
+
+impl Options {
+    /// Parse the command line arguments
+    ///
+    /// Prints the usage and exits on `--help` or invalid arguments.
+    pub fn parse() -> Options {
+        match Self::parse_from(env::args().skip(1)) {
+            Ok(Some(options)) => options,
+            Ok(None) => {
+                print!("{USAGE}");
+                std::process::exit(0);
+            }
+            Err(err) => {
+                eprint!("kmailight: {err}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+
+    /// Parse the given arguments, returns `None` if help was requested
+    fn parse_from(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+        let mut options = Options::default();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(None),
+                "-m" | "--mbox" => options.mbox = true,
+                "-q" | "--quote" => {
+                    let name = args
+                        .next()
+                        .ok_or_else(|| format!("option '{arg}' requires a profile"))?;
+                    options.quote = QuoteProfile::from_name(&name).ok_or_else(|| {
+                        format!(
+                            "unknown quote profile '{name}' (expected one of: {})",
+                            QuoteProfile::NAMES.join(", ")
+                        )
+                    })?;
+                }
+                "-t" | "--theme" => {
+                    let name = args
+                        .next()
+                        .ok_or_else(|| format!("option '{arg}' requires a theme"))?;
+                    options.theme = Some(name);
+                }
+                "-" => options.path = None,
+                _ if arg.starts_with('-') => return Err(format!("unknown option '{arg}'")),
+                _ if options.path.is_some() => {
+                    return Err(format!("unexpected argument '{arg}'"));
+                }
+                _ => options.path = Some(arg),
+            }
+        }
+
+        if let Some(path) = &options.path
+            && (path.ends_with(".mbox") || path.ends_with(".mbox.gz"))
+        {
+            options.mbox = true;
+        }
+        Ok(Some(options))
+    }
+}
@@ -1,7 +1,12 @@
 #![allow(dead_code)]
 
+use crate::parser::header::Headers;
 use crate::parser::line::Line;
+use crate::parser::mime::MimePart;
 use crate::parser::span::Span;
+use crate::theme::Theme;
+#[cfg(debug_assertions)]
+use crate::theme::{RESET, Style};
 
 #[cfg(debug_assertions)]
 /// Dump the tree for debugging
@@ -10,7 +15,7 @@ pub fn dump_tree(node: tree_sitter::Node, source: &str) {
         let indent_str = "  ".repeat(indent);
         let kind = node.kind();
         let span = &source[node.start_byte()..node.end_byte()];
-        println!("{indent_str}{kind}: {:?}", span.trim());
+        eprintln!("{indent_str}{kind}: {:?}", span.trim());
 
         let mut cursor = node.walk();
         for child in node.children(&mut cursor) {
@@ -22,11 +27,10 @@ pub fn dump_tree(node: tree_sitter::Node, source: &str) {
 
 #[cfg(debug_assertions)]
 /// Pretty-print the valid and invalid chunks returned by `collect_non_error_chunks`.
-pub fn print_chunks(chunks: &[(usize, usize)], src: &str) {
-    const RED: &str = "\x1b[1;31m";
-    const RESET: &str = "\x1b[0m";
+pub fn print_chunks(chunks: &[(usize, usize)], src: &str, theme: &Theme) {
+    let error = theme.message.warning.escape();
 
-    println!("\n{:=^80}", " Chunks ");
+    eprintln!("\n{:=^80}", " Chunks ");
 
     /* True means the chunk is invalid (ERROR) */
     let mut all_chunks = Vec::new();
@@ -51,29 +55,78 @@ pub fn print_chunks(chunks: &[(usize, usize)], src: &str) {
 
         let label = if is_error { "ERROR" } else { "" };
         let prefix = format!(
-            " {idx:>3}. [{start:>5},{end:<5}] len={len:<5} {RED}{label:<6}{RESET}  ",
+            " {idx:>3}. [{start:>5},{end:<5}] len={len:<5} {error}{label:<6}{RESET}  ",
             idx = i + 1,
             start = start,
-            end = end - 1,
+            end = end.saturating_sub(1),
             len = len,
             label = label
         );
 
         if let Some(first_line) = lines.next() {
-            print!("{prefix}");
-            println!("{first_line}");
+            eprint!("{prefix}");
+            eprintln!("{first_line}");
 
-            let indent_width = prefix.chars().count() - RED.len() - RESET.len();
+            let indent_width = prefix.chars().count() - error.len() - RESET.len();
             let code_offset = first_line.chars().take_while(|c| c.is_whitespace()).count();
             let indent = " ".repeat(indent_width + code_offset);
 
             for line in lines {
-                println!("{indent}{line}");
+                eprintln!("{indent}{line}");
             }
         }
     }
 
-    println!("{:=^80}\n", "");
+    eprintln!("{:=^80}\n", "");
+}
+
+#[cfg(debug_assertions)]
+/// Pretty-print the parsed email header block
+pub fn print_headers(headers: &Headers) {
+    eprintln!("\n{:=^80}", " Headers ");
+
+    let width = headers
+        .fields
+        .iter()
+        .map(|f| f.name.len())
+        .max()
+        .unwrap_or(0);
+    for field in &headers.fields {
+        eprintln!(
+            " off:{:>5}  len:{:>4}  {:<width$}  {:?}",
+            field.offset,
+            field.length,
+            field.name,
+            field.value,
+            width = width
+        );
+    }
+    eprintln!(
+        " subject: {:?}  message-id: {:?}  in-reply-to: {:?}",
+        headers.subject(),
+        headers.message_id(),
+        headers.in_reply_to()
+    );
+
+    eprintln!("{:=^80}\n", "");
+}
+
+#[cfg(debug_assertions)]
+/// Pretty-print the decoded MIME parts of a message
+pub fn print_parts(parts: &[MimePart]) {
+    eprintln!("\n{:=^80}", " MIME parts ");
+
+    for (i, part) in parts.iter().enumerate() {
+        eprintln!(
+            " {idx:>3}. decoded={len:<5} {ctype} {name}",
+            idx = i + 1,
+            len = part.text.len(),
+            ctype = part.content_type,
+            name = part.filename.as_deref().unwrap_or(""),
+        );
+    }
+
+    eprintln!("{:=^80}\n", "");
 }
 
 #[cfg(debug_assertions)]
@@ -98,6 +151,17 @@ pub fn print_lines(lines: &[Line]) {
 
     fn format_prefix(line: &Line) -> String {
         match line {
+            Line::Header {
+                offset,
+                quoting_layer,
+                length,
+                ..
+            } => {
+                format!(
+                    "HDR  off:{:>5}  q:{:<2}  len:{:>4}",
+                    offset, quoting_layer, length
+                )
+            }
             Line::Text {
                 offset,
                 quoting_layer,
@@ -109,30 +173,70 @@ pub fn print_lines(lines: &[Line]) {
                     offset, quoting_layer, length
                 )
             }
+            Line::Separator {
+                offset,
+                quoting_layer,
+                length,
+                ..
+            } => {
+                format!(
+                    "SEP  off:{:>5}  q:{:<2}  len:{:>4}",
+                    offset, quoting_layer, length
+                )
+            }
+            Line::DiffStat {
+                offset,
+                quoting_layer,
+                length,
+                ..
+            } => {
+                format!(
+                    "STAT off:{:>5}  q:{:<2}  len:{:>4}",
+                    offset, quoting_layer, length
+                )
+            }
+            Line::Signature {
+                offset,
+                quoting_layer,
+                length,
+                ..
+            } => {
+                format!(
+                    "SIG  off:{:>5}  q:{:<2}  len:{:>4}",
+                    offset, quoting_layer, length
+                )
+            }
             Line::DiffHeader {
                 offset,
                 quoting_layer,
-                file_path,
+                old_path,
+                new_path,
                 length,
                 ..
             } => {
+                let file = if old_path == new_path {
+                    shorten(new_path, 20)
+                } else {
+                    format!("{} -> {}", shorten(old_path, 20), shorten(new_path, 20))
+                };
                 format!(
                     "DIFF off:{:>5}  q:{:<2}  len:{:>4}              file:{:<20}",
-                    offset,
-                    quoting_layer,
-                    length,
-                    shorten(file_path, 20)
+                    offset, quoting_layer, length, file
                 )
             }
             Line::DiffMetadata {
                 offset,
                 quoting_layer,
                 length,
+                kind,
                 ..
             } => {
                 format!(
-                    "META off:{:>5}  q:{:<2}  len:{:>4}",
-                    offset, quoting_layer, length
+                    "META off:{:>5}  q:{:<2}  len:{:>4}  kind:{:<7}",
+                    offset,
+                    quoting_layer,
+                    length,
+                    shorten(&format!("{:?}", kind), 40)
                 )
             }
             Line::HunkHeader {
@@ -141,15 +245,48 @@ pub fn print_lines(lines: &[Line]) {
                 file_path,
                 language,
                 length,
+                old_ranges,
+                new_range,
+                function_context,
+                damage,
                 ..
             } => {
                 format!(
-                    "HUNK off:{:>5}  q:{:<2}  len:{:>4}              file:{:<20} lang:{:<7}",
+                    "HUNK off:{:>5}  q:{:<2}  len:{:>4}              file:{:<20} lang:{:<7} range:{:<15} ctx:{:<20}{}",
                     offset,
                     quoting_layer,
                     length,
                     shorten(file_path, 20),
                     language,
+                    format!(
+                        "{} +{},{}",
+                        old_ranges
+                            .iter()
+                            .map(|r| format!("-{},{}", r.start, r.count))
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        new_range.start,
+                        new_range.count
+                    ),
+                    shorten(function_context, 20),
+                    damage
+                        .map(|d| format!(" damage:{:?}", d))
+                        .unwrap_or_default()
+                )
+            }
+            Line::BinaryPatch {
+                offset,
+                quoting_layer,
+                length,
+                kind,
+                ..
+            } => {
+                format!(
+                    "BIN  off:{:>5}  q:{:<2}  len:{:>4}  kind:{:<7}",
+                    offset,
+                    quoting_layer,
+                    length,
+                    format!("{:?}", kind)
                 )
             }
             Line::Code {
@@ -159,16 +296,24 @@ pub fn print_lines(lines: &[Line]) {
                 file_path,
                 language,
                 length,
+                damage,
+                continued_from,
                 ..
             } => {
                 format!(
-                    "CODE off:{:>5}  q:{:<2}  len:{:>4}  kind:{:<7} file:{:<20} lang:{:<7}",
+                    "CODE off:{:>5}  q:{:<2}  len:{:>4}  kind:{:<7} file:{:<20} lang:{:<7}{}{}",
                     offset,
                     quoting_layer,
                     length,
                     format!("{:?}", kind),
                     shorten(file_path, 20),
-                    language
+                    language,
+                    damage
+                        .map(|d| format!(" damage:{:?}", d))
+                        .unwrap_or_default(),
+                    continued_from
+                        .map(|q| format!(" from:q{}", q))
+                        .unwrap_or_default()
                 )
             }
         }
@@ -178,13 +323,7 @@ pub fn print_lines(lines: &[Line]) {
         .iter()
         .map(|line| {
             let prefix = format_prefix(line);
-            let raw = match line {
-                Line::Text { raw, .. }
-                | Line::DiffHeader { raw, .. }
-                | Line::DiffMetadata { raw, .. }
-                | Line::HunkHeader { raw, .. }
-                | Line::Code { raw, .. } => preview(raw),
-            };
+            let raw = preview(line.get_raw());
             (prefix, raw)
         })
         .collect();
@@ -192,77 +331,51 @@ pub fn print_lines(lines: &[Line]) {
     let max_prefix_len = rows.iter().map(|(p, _)| p.len()).max().unwrap_or(0);
 
     for (prefix, raw) in rows {
-        println!("{:<width$}  raw: {}", prefix, raw, width = max_prefix_len);
+        eprintln!("{:<width$}  raw: {}", prefix, raw, width = max_prefix_len);
     }
 }
 
 #[cfg(debug_assertions)]
-pub fn print_spans<'a>(spans: &[Span<'a>]) {
-    const RESET: &str = "\x1b[0m";
-    const CYAN: &str = "\x1b[1;36m";
-    const YELLOW: &str = "\x1b[1;33m";
-    const GREEN: &str = "\x1b[1;32m";
-    const MAGENTA: &str = "\x1b[1;35m";
-
+pub fn print_spans<'a>(spans: &[Span<'a>], theme: &Theme) {
     fn join_span_lines<'a>(span: &Span<'a>) -> String {
-        match span {
-            Span::Text { lines, .. }
-            | Span::DiffHeader { lines, .. }
-            | Span::DiffMetadata { lines, .. }
-            | Span::HunkHeader { lines, .. }
-            | Span::Code { lines, .. } => lines
-                .iter()
-                .map(|l| l.get_raw())
-                .collect::<Vec<_>>()
-                .join("\n"),
-        }
+        span.get_lines()
+            .iter()
+            .map(|l| l.get_raw())
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 
-    println!("\n{:=^172}", " Spans ");
+    eprintln!("\n{:=^172}", " Spans ");
 
     for (i, span) in spans.iter().enumerate() {
-        let (start, end, q, label, color) = match span {
-            Span::Text {
-                start,
-                end,
-                quoting_layer,
-                ..
-            } => (*start, *end, *quoting_layer, "TEXT", CYAN),
-            Span::DiffHeader {
-                start,
-                end,
-                quoting_layer,
-                ..
-            } => (*start, *end, *quoting_layer, "DIFF", MAGENTA),
-            Span::DiffMetadata {
-                start,
-                end,
-                quoting_layer,
-                ..
-            } => (*start, *end, *quoting_layer, "META", MAGENTA),
-            Span::HunkHeader {
-                start,
-                end,
-                quoting_layer,
-                ..
-            } => (*start, *end, *quoting_layer, "HUNK", YELLOW),
-            Span::Code {
-                start,
-                end,
-                quoting_layer,
-                ..
-            } => (*start, *end, *quoting_layer, "CODE", GREEN),
+        /* Each label is painted like its span */
+        let q = span.get_lines()[0].get_quoting_layer();
+        let (start, end, label, style): (_, _, _, Style) = match span {
+            Span::Headers { start, end, .. } => (*start, *end, "HDRS", theme.headers.name),
+            Span::Text { start, end, .. } => (*start, *end, "TEXT", theme.quote_style(q)),
+            Span::Separator { start, end, .. } => (*start, *end, "SEP", theme.message.separator),
+            Span::DiffStat { start, end, .. } => (*start, *end, "STAT", theme.diff.add),
+            Span::Signature { start, end, .. } => (*start, *end, "SIG", theme.message.signature),
+            Span::DiffHeader { start, end, .. } => (*start, *end, "DIFF", theme.diff.header),
+            Span::DiffMetadata { start, end, .. } => (*start, *end, "META", theme.metadata.file),
+            Span::HunkHeader { start, end, .. } => (*start, *end, "HUNK", theme.diff.hunk_header),
+            Span::BinaryPatch { start, end, .. } => {
+                (*start, *end, "BIN", theme.message.binary_fold)
+            }
+            Span::Code { start, end, .. } => (*start, *end, "CODE", theme.diff.add),
         };
 
         let raw = join_span_lines(span);
         let mut lines = raw.lines();
 
         let len = end - start;
+        let color = style.escape();
+        let reset = if color.is_empty() { "" } else { RESET };
         let prefix = format!(
-            " {idx:>3}. [{start:>5},{end:<5}] len={len:<5} q={q:<2} {color}{label:<6}{RESET}  ",
+            " {idx:>3}. [{start:>5},{end:<5}] len={len:<5} q={q:<2} {color}{label:<6}{reset}  ",
             idx = i + 1,
             start = start,
-            end = end - 1,
+            end = end.saturating_sub(1),
             len = len,
             q = q,
             label = label,
@@ -270,20 +383,20 @@ pub fn print_spans<'a>(spans: &[Span<'a>]) {
         );
 
         if let Some(first_line) = lines.next() {
-            print!("{prefix}");
-            println!("{first_line}");
+            eprint!("{prefix}");
+            eprintln!("{first_line}");
 
-            let indent_width = prefix.chars().count() - RESET.len() - color.len();
+            let indent_width = prefix.chars().count() - reset.len() - color.len();
             let code_offset = first_line.chars().take_while(|c| c.is_whitespace()).count();
             let indent = " ".repeat(indent_width + code_offset);
 
             for line in lines {
-                println!("{indent}{line}");
+                eprintln!("{indent}{line}");
             }
         }
     }
 
-    println!("{:=^172}\n", "");
+    eprintln!("{:=^172}\n", "");
 }
 
 #[cfg(not(debug_assertions))]
@@ -294,7 +407,17 @@ pub fn dump_tree(_: tree_sitter::Node, _: &str) {}
 #[cfg(not(debug_assertions))]
 #[inline(always)]
 /// No-op for release builds
-pub fn print_chunks(_: &[(usize, usize)], _: &str) {}
+pub fn print_chunks(_: &[(usize, usize)], _: &str, _: &Theme) {}
+
+#[cfg(not(debug_assertions))]
+#[inline(always)]
+/// No-op for release builds
+pub fn print_headers(_: &Headers) {}
+
+#[cfg(not(debug_assertions))]
+#[inline(always)]
+/// No-op for release builds
+pub fn print_parts(_: &[MimePart]) {}
 
 #[cfg(not(debug_assertions))]
 #[inline(always)]
@@ -304,4 +427,4 @@ pub fn print_lines(_: &Vec<Line>) {}
 #[cfg(not(debug_assertions))]
 #[inline(always)]
 /// No-op for release builds
-pub fn print_spans(_: &Vec<Span>) {}
+pub fn print_spans(_: &[Span], _: &Theme) {}
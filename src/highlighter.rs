@@ -1,125 +1,578 @@
 use std::collections::HashMap;
+use std::ops::Range;
+use tree_sitter::{Query, QueryCursor, StreamingIterator};
 use tree_sitter_highlight::{HighlightConfiguration, HighlightEvent, Highlighter};
 
+use crate::debug;
+use crate::injection;
+use crate::language;
+use crate::parser::header::{split_field, subject_tags_len};
+use crate::parser::line::{BinaryKind, CodeKind, Line, MetadataKind};
+use crate::parser::span::Span;
+use crate::theme::{RESET, Style, Theme};
+
+/// Capture names recognized in the highlight queries, their index is the class of a region
+///
+/// tree-sitter maps every other capture to the most specific of these it
+/// extends (`function.macro.call` is `function.macro`), and the theme falls
+/// back to the parent of a name it has no style for (see `Theme::syntax_style`).
+const HIGHLIGHT_NAMES: &[&str] = &[
+    "attribute",
+    "attribute.builtin",
+    "boolean",
+    "character",
+    "character.special",
+    "comment",
+    "comment.documentation",
+    "constant",
+    "constant.builtin",
+    "constant.character",
+    "constant.character.escape",
+    "constant.macro",
+    "constant.numeric",
+    "constructor",
+    "delimiter",
+    "embedded",
+    "escape",
+    "function",
+    "function.builtin",
+    "function.call",
+    "function.macro",
+    "function.method",
+    "function.method.call",
+    "function.special",
+    "keyword",
+    "keyword.conditional",
+    "keyword.control",
+    "keyword.directive",
+    "keyword.function",
+    "keyword.import",
+    "keyword.operator",
+    "keyword.repeat",
+    "keyword.return",
+    "keyword.storage",
+    "keyword.type",
+    "label",
+    "module",
+    "namespace",
+    "number",
+    "number.float",
+    "operator",
+    "property",
+    "property.builtin",
+    "punctuation",
+    "punctuation.bracket",
+    "punctuation.delimiter",
+    "punctuation.special",
+    "string",
+    "string.escape",
+    "string.regexp",
+    "string.special",
+    "string.special.key",
+    "string.special.path",
+    "string.special.symbol",
+    "string.special.url",
+    "tag",
+    "tag.attribute",
+    "text",
+    "text.emphasis",
+    "text.literal",
+    "text.reference",
+    "text.strong",
+    "text.title",
+    "text.uri",
+    "type",
+    "type.builtin",
+    "type.definition",
+    "variable",
+    "variable.builtin",
+    "variable.member",
+    "variable.parameter",
+    /* Kernel idioms (see `queries/c/kernel.scm`) */
+    "attribute.kernel",
+    "keyword.hint",
+    "function.export",
+    "function.module",
+    "keyword.iterator",
+    "function.log",
+    "function.config",
+    "constant.config",
+];
+
+/// A highlighted byte range of a source buffer
+///
+/// `class` is the innermost active highlight the theme paints, or `None`
+/// for plain source.
+#[derive(Debug, Clone, Copy)]
+struct Region {
+    start: usize,
+    end: usize,
+    class: Option<usize>,
+}
+
+/// One of the two virtual files reconstructed from a hunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Image {
+    /// Context and removed lines (the file before the change)
+    Pre,
+    /// Context and added lines (the file after the change)
+    Post,
+}
+
 pub struct HighlighterEngine {
     configs: HashMap<&'static str, HighlightConfiguration>,
+    /// Injection queries of the languages that inject a language with a fallback
+    injection_queries: HashMap<&'static str, Query>,
+    theme: Theme,
+    /// Escape sequence of each class (see `HIGHLIGHT_NAMES`), empty if it is not painted
+    styles: Vec<String>,
     highlighter: Highlighter,
 }
 
 impl HighlighterEngine {
-    pub fn new() -> Self {
+    pub fn new(theme: Theme) -> Self {
         let mut configs = HashMap::new();
+        let mut injection_queries = HashMap::new();
 
-        let mut c_config = HighlightConfiguration::new(
-            tree_sitter_c::LANGUAGE.into(),
-            "c",
-            tree_sitter_c::HIGHLIGHT_QUERY,
-            "",
-            tree_sitter_c::TAGS_QUERY,
-        )
-        .unwrap();
-        c_config.configure(&[
-            "function", "type", "string", "keyword", "number", "comment", "constant", "operator",
-            "variable",
-        ]);
-        configs.insert("c", c_config);
+        for lang in language::languages() {
+            let Some(grammar) = &lang.grammar else {
+                continue;
+            };
+            /* The layer comes last, as the last pattern matching a node wins */
+            let highlights = format!("{}\n{}", grammar.highlights, grammar.highlight_layer);
+            let injections = format!("{}\n{}", grammar.injections, grammar.injection_layer);
+
+            /* Queries from the user config can be broken, that language is not highlighted then */
+            let mut config = match HighlightConfiguration::new(
+                grammar.language.into(),
+                lang.id,
+                &highlights,
+                &injections,
+                grammar.locals,
+            ) {
+                Ok(config) => config,
+                Err(err) => {
+                    eprintln!(
+                        "kmailight: invalid queries for language '{}': {err}",
+                        lang.id
+                    );
+                    continue;
+                }
+            };
+            config.configure(HIGHLIGHT_NAMES);
+
+            /* tree-sitter-highlight skips the injections it has no config for, they are run again */
+            if injection::FALLBACKS
+                .iter()
+                .any(|name| injections.contains(name))
+                && let Ok(query) = Query::new(&config.language, &injections)
+            {
+                injection_queries.insert(lang.id, query);
+            }
+            configs.insert(lang.id, config);
+        }
+
+        let styles = HIGHLIGHT_NAMES
+            .iter()
+            .map(|name| {
+                theme
+                    .syntax_style(name)
+                    .map(|s| s.escape())
+                    .unwrap_or_default()
+            })
+            .collect();
 
         Self {
             configs,
+            injection_queries,
+            theme,
+            styles,
             highlighter: Highlighter::new(),
         }
     }
 
+    /// Get the theme the output is painted with
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
     /// Highlight an individual hunk of code
-    pub fn highlight_code(&mut self, lang: &str, code: &str) -> String {
-        let Some(config) = self.configs.get(lang) else {
-            return code.to_string();
+    ///
+    /// The quote prefix and the diff marker of each line are stripped before
+    /// parsing, so tree-sitter only ever sees clean source code. They are
+    /// painted separately and re-injected in front of every highlighted line.
+    ///
+    /// Interleaved additions and removals do not form valid code on their own,
+    /// so the hunk is split into two virtual files: the pre-image (context and
+    /// removed lines) and the post-image (context and added lines). Each one is
+    /// highlighted separately, then removed lines are painted from the pre-image
+    /// and everything else from the post-image. For combined diffs, the pre-image
+    /// holds the lines of all parents and the post-image is the merge result.
+    /// The `\ No newline at end of file` marker is in neither, it is dimmed and
+    /// tinted like the line it applies to. Lines damaged by a mail client (see
+    /// `Damage`) are painted as warnings.
+    pub fn highlight_code(&mut self, span: &Span) -> String {
+        let lines = span.get_lines();
+        let (language, context) = match lines.first() {
+            Some(Line::Code {
+                language,
+                function_context,
+                ..
+            }) => (language.as_str(), function_context.as_str()),
+            _ => (language::UNKNOWN, ""),
         };
 
-        let events = self
-            .highlighter
-            .highlight(config, code.as_bytes(), None, |_| None)
-            .unwrap();
+        let (pre, post, placements) = build_images(lines);
+        let pre_regions = self.collect_regions(language, context, &pre);
+        let post_regions = self.collect_regions(language, context, &post);
 
-        let mut output = String::new();
-        for event in events {
-            match event.unwrap() {
-                HighlightEvent::Source { start, end } => {
-                    output.push_str(&code[start..end]);
+        let theme = &self.theme;
+        let mut output = String::with_capacity((pre.len() + post.len()) * 2);
+        for (i, line) in lines.iter().enumerate() {
+            let (prefix, marker, code) = line.split_code().unwrap_or_default();
+            output.push_str(&paint_quote_line(theme, prefix));
+            output.push_str(&paint_marker(theme, marker));
+
+            match placements[i] {
+                /* Damaged lines are not trustworthy code, they are flagged instead */
+                Some(_)
+                    if matches!(
+                        line,
+                        Line::Code {
+                            damage: Some(_),
+                            ..
+                        }
+                    ) =>
+                {
+                    output.push_str(&theme.message.warning.paint(code));
                 }
-                HighlightEvent::HighlightStart(s) => {
-                    output.push_str(ansi_for_class(s.0));
+                Some((image, start)) => {
+                    let (source, regions) = match image {
+                        Image::Pre => (&pre, &pre_regions),
+                        Image::Post => (&post, &post_regions),
+                    };
+                    let background = match line {
+                        Line::Code { kind, .. } => line_style(theme, kind).background().escape(),
+                        _ => String::new(),
+                    };
+                    paint_regions(
+                        &mut output,
+                        source,
+                        regions,
+                        &self.styles,
+                        &background,
+                        start..start + code.len(),
+                    );
                 }
-                HighlightEvent::HighlightEnd => {
-                    output.push_str("\x1b[0m");
+                None => {
+                    /* Tint the marker like the side of the line it applies to */
+                    let no_newline = theme.message.no_newline;
+                    let tint = match line {
+                        Line::Code {
+                            kind: CodeKind::NoNewline(kind),
+                            ..
+                        } => match **kind {
+                            CodeKind::Add => theme.diff.add.fg,
+                            CodeKind::Remove => theme.diff.remove.fg,
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    let style = Style {
+                        fg: tint.or(no_newline.fg),
+                        ..no_newline
+                    };
+                    output.push_str(&style.paint(code));
                 }
             }
+
+            if i + 1 < lines.len() {
+                output.push_str(line_terminator(line));
+            }
         }
         output
     }
 
-    /// Helper function to highlight quoting marks
-    fn highlight_quoting_marks(&mut self, text: &str) -> String {
-        const BLUE: &str = "\x1b[34m";
-        const RESET: &str = "\x1b[0m";
+    /// Highlight `code`, recovering as much as possible from partial code
+    ///
+    /// Hunks are fragments: they start in the middle of a function, inside
+    /// a comment, etc. If `code` does not parse cleanly on its own, it is
+    /// first re-parsed inside synthetic wrappers (see `synthetic_wrappers`).
+    /// If none of them help, the code is split into chunks free of ERROR nodes
+    /// which keep their highlighting, while the ERROR regions in between are
+    /// re-parsed with the wrappers on their own or left plain.
+    ///
+    /// `context` is the function context of the hunk header. If it is present,
+    /// the code is first wrapped in it (see `context_wrapper`), so statements
+    /// are parsed as the body of the function they really belong to.
+    fn collect_regions(&mut self, lang: &str, context: &str, code: &str) -> Vec<Region> {
+        if !self.configs.contains_key(lang) {
+            return plain_region(0, code.len());
+        }
 
-        /// Paint only the quote marks at the beginning of the quoted lines
-        #[cfg(not(feature = "quote-paint-full"))]
-        fn paint_line(line: &str) -> String {
-            let mut out = String::with_capacity(line.len() + 8);
-            let mut started = false;
+        if let Some((prefix, suffix)) = context_wrapper(lang, context, code)
+            && let Some(regions) = self.highlight_wrapped(lang, code, &prefix, &suffix)
+        {
+            return regions;
+        }
+        if let Some(regions) = self.highlight_wrapped(lang, code, "", "") {
+            return regions;
+        }
+        if let Some(split) = comment_tail_len(lang, code) {
+            let mut regions = self
+                .highlight_wrapped(lang, &code[..split], "/*", "")
+                .unwrap_or_else(|| plain_region(0, split));
+            regions.extend(shift_regions(
+                self.collect_regions(lang, context, &code[split..]),
+                split,
+            ));
+            return regions;
+        }
+        for (prefix, suffix) in synthetic_wrappers(lang, code) {
+            if let Some(regions) = self.highlight_wrapped(lang, code, &prefix, &suffix) {
+                return regions;
+            }
+        }
 
-            for (idx, ch) in line.char_indices() {
-                match ch {
-                    ' ' | '\t' if !started => {
-                        out.push(ch);
-                    }
-                    '>' if !started => {
-                        out.push_str(BLUE);
-                        out.push('>');
-                        out.push_str(RESET);
-                    }
-                    _ => {
-                        out.push_str(&line[idx..]);
-                        return out;
+        let Some(tree) = self.parse(lang, code) else {
+            return plain_region(0, code.len());
+        };
+        let chunks = collect_non_error_chunks(lang, tree.root_node(), code);
+        debug::print_chunks(&chunks, code, &self.theme);
+
+        let full = self.highlight_source(lang, code);
+        let mut regions = Vec::new();
+        let mut last_end = 0;
+        for (start, end) in chunks {
+            if start > last_end {
+                regions.extend(self.recover_error_region(lang, context, code, last_end, start));
+            }
+            regions.extend(clip_regions(&full, start, end));
+            last_end = end;
+        }
+        if last_end < code.len() {
+            regions.extend(self.recover_error_region(lang, context, code, last_end, code.len()));
+        }
+        regions
+    }
+
+    /// Try to highlight the `[start, end)` ERROR region of `code` on its own
+    fn recover_error_region(
+        &mut self,
+        lang: &str,
+        context: &str,
+        code: &str,
+        start: usize,
+        end: usize,
+    ) -> Vec<Region> {
+        let fragment = &code[start..end];
+        if fragment.trim().is_empty() {
+            return plain_region(start, end);
+        }
+
+        let wrappers = context_wrapper(lang, context, fragment)
+            .into_iter()
+            .chain(synthetic_wrappers(lang, fragment));
+        for (prefix, suffix) in wrappers {
+            if let Some(regions) = self.highlight_wrapped(lang, fragment, &prefix, &suffix) {
+                return shift_regions(regions, start);
+            }
+        }
+        plain_region(start, end)
+    }
+
+    /// Highlight `code` wrapped between `prefix` and `suffix`
+    ///
+    /// Returns `None` if the wrapped code does not parse without errors.
+    /// The returned regions are relative to `code`, not to the wrapped source.
+    fn highlight_wrapped(
+        &mut self,
+        lang: &str,
+        code: &str,
+        prefix: &str,
+        suffix: &str,
+    ) -> Option<Vec<Region>> {
+        let wrapped = format!("{prefix}{code}{suffix}");
+        let tree = self.parse(lang, &wrapped)?;
+        if has_real_error(lang, tree.root_node()) {
+            return None;
+        }
+
+        let regions = self.highlight_source(lang, &wrapped);
+        let offset = prefix.len();
+        Some(
+            clip_regions(&regions, offset, offset + code.len())
+                .into_iter()
+                .map(|r| Region {
+                    start: r.start - offset,
+                    end: r.end - offset,
+                    class: r.class,
+                })
+                .collect(),
+        )
+    }
+
+    /// Parse `code` with the grammar of the given language
+    fn parse(&mut self, lang: &str, code: &str) -> Option<tree_sitter::Tree> {
+        let config = self.configs.get(lang)?;
+        let parser = self.highlighter.parser();
+        parser.set_language(&config.language).ok()?;
+        parser.parse(code, None)
+    }
+
+    /// Run tree-sitter on `code` and flatten the highlight events into regions
+    ///
+    /// Injected languages are highlighted with their own config, looked up by
+    /// id or by extension (see `language::by_name`), or with their built-in
+    /// fallback (see `injection`). Falls back to a single plain region if the
+    /// language is not supported or highlighting fails.
+    fn highlight_source(&mut self, lang: &str, code: &str) -> Vec<Region> {
+        let Self {
+            configs,
+            highlighter,
+            ..
+        } = self;
+        let Some(config) = configs.get(lang) else {
+            return plain_region(0, code.len());
+        };
+        let Ok(events) = highlighter.highlight(config, code.as_bytes(), None, |name| {
+            injection_config(configs, name)
+        }) else {
+            return plain_region(0, code.len());
+        };
+
+        let mut regions: Vec<Region> = Vec::new();
+        let mut stack = Vec::new();
+        for event in events {
+            match event {
+                Ok(HighlightEvent::Source { start, end }) => {
+                    let class = stack.last().copied().flatten();
+                    match regions.last_mut() {
+                        Some(last) if last.class == class && last.end == start => last.end = end,
+                        _ => regions.push(Region { start, end, class }),
                     }
                 }
-                if ch != ' ' && ch != '\t' {
-                    started = true;
+                /* Captures the theme does not paint let the enclosing one show through */
+                Ok(HighlightEvent::HighlightStart(s)) => {
+                    let class = if self.styles[s.0].is_empty() {
+                        stack.last().copied().flatten()
+                    } else {
+                        Some(s.0)
+                    };
+                    stack.push(class);
+                }
+                Ok(HighlightEvent::HighlightEnd) => {
+                    stack.pop();
                 }
+                Err(_) => return plain_region(0, code.len()),
             }
-            out
         }
 
-        /// Paint the full quoted lines
-        #[cfg(feature = "quote-paint-full")]
-        fn paint_line(line: &str) -> String {
-            const BLUE: &str = "\x1b[34m";
-            const RESET: &str = "\x1b[0m";
+        let fallbacks = self.fallback_injection_regions(lang, code);
+        if fallbacks.is_empty() {
+            regions
+        } else {
+            overlay_regions(&regions, &fallbacks)
+        }
+    }
 
-            let bytes = line.as_bytes();
-            let mut i = 0;
-            while i < bytes.len() && (bytes[i] == b' ' || bytes[i] == b'\t') {
-                i += 1;
+    /// Highlight the injected languages of `code` that only have a built-in fallback
+    ///
+    /// Returns sorted, non-overlapping regions, to be laid over the regions
+    /// of the host language.
+    fn fallback_injection_regions(&mut self, lang: &str, code: &str) -> Vec<Region> {
+        if !self.injection_queries.contains_key(lang) {
+            return Vec::new();
+        }
+        let Some(tree) = self.parse(lang, code) else {
+            return Vec::new();
+        };
+        let query = &self.injection_queries[lang];
+        let Some(content) = query.capture_index_for_name("injection.content") else {
+            return Vec::new();
+        };
+
+        let mut regions = Vec::new();
+        let mut cursor = QueryCursor::new();
+        let mut matches = cursor.matches(query, tree.root_node(), code.as_bytes());
+        while let Some(m) = matches.next() {
+            let Some(name) = query
+                .property_settings(m.pattern_index)
+                .iter()
+                .find(|p| &*p.key == "injection.language")
+                .and_then(|p| p.value.as_deref())
+            else {
+                continue;
+            };
+            if injection_config(&self.configs, name).is_some() {
+                continue;
             }
-            let mut j = i;
-            while j < bytes.len() && bytes[j] == b'>' {
-                j += 1;
+            for capture in m.captures.iter().filter(|c| c.index == content) {
+                let start = capture.node.start_byte();
+                let Some(spans) = injection::fallback(name, &code[capture.node.byte_range()])
+                else {
+                    continue;
+                };
+                regions.extend(spans.into_iter().filter_map(|(from, to, capture)| {
+                    Some(Region {
+                        start: start + from,
+                        end: start + to,
+                        class: Some(class_of(capture).filter(|&c| !self.styles[c].is_empty())?),
+                    })
+                }));
             }
+        }
 
-            if j == i {
-                return line.to_string();
+        regions.sort_by_key(|r| r.start);
+        let mut end = 0;
+        regions.retain(|r| {
+            let keep = r.start >= end;
+            if keep {
+                end = r.end;
             }
+            keep
+        });
+        regions
+    }
 
-            let mut out = String::with_capacity(line.len() + BLUE.len() + RESET.len());
-            out.push_str(BLUE);
-            out.push_str(line);
-            out.push_str(RESET);
-            out
-        }
+    /// Highlight the email header block
+    ///
+    /// Field names are painted apart from their values, and the tags at the
+    /// start of the subject (`[PATCH v3 2/7]`, `Re:`) stand out. The mbox
+    /// envelope line (`From <sha> <date>`) is dimmed.
+    pub fn highlight_headers(&mut self, headers: &str) -> String {
+        let styles = &self.theme.headers;
+        let ends_with_nl = headers.ends_with('\n');
+        let mut result = headers
+            .lines()
+            .map(|line| {
+                if line.starts_with([' ', '\t']) {
+                    return line.to_string();
+                }
+                if line.starts_with("From ") {
+                    return styles.envelope.paint(line);
+                }
+                let Some((name, value)) = split_field(line) else {
+                    return line.to_string();
+                };
 
-        let ends_with_nl = text.ends_with('\n');
-        let mut result = text.lines().map(paint_line).collect::<Vec<_>>().join("\n");
+                let value_start = value.len() - value.trim_start().len();
+                let (space, value) = value.split_at(value_start);
+                let tags_len = if name.eq_ignore_ascii_case("Subject") {
+                    subject_tags_len(value)
+                } else {
+                    0
+                };
+                let (tags, rest) = value.split_at(tags_len);
+                let name = styles.name.paint(&format!("{name}:"));
+                if tags.is_empty() {
+                    format!("{name}{space}{rest}")
+                } else {
+                    format!("{name}{space}{}{rest}", styles.subject_tags.paint(tags))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
 
         if ends_with_nl {
             result.push('\n');
@@ -127,41 +580,974 @@ impl HighlighterEngine {
         result
     }
 
+    /// Create the separator line shown in front of a message of an mbox
+    pub fn highlight_msgsep(
+        &mut self,
+        index: usize,
+        total: usize,
+        subject: Option<&str>,
+    ) -> String {
+        let title = match subject {
+            Some(subject) => format!(" [{index}/{total}] {subject} "),
+            None => format!(" [{index}/{total}] "),
+        };
+        self.theme.message.mbox_separator.paint(&format!(
+            "━━━━{title}{}",
+            "━".repeat(72usize.saturating_sub(title.chars().count()))
+        ))
+    }
+
+    /// Create the separator line shown in front of a part of a multipart message
+    pub fn highlight_partsep(&mut self, content_type: &str, filename: Option<&str>) -> String {
+        let title = match filename {
+            Some(filename) => format!("──── {content_type}: {filename} ────"),
+            None => format!("──── {content_type} ────"),
+        };
+        self.theme.message.part_separator.paint(&title)
+    }
+
     /// Highlight an individual hunk of text
     ///
     /// The only thing highlighted for now are the quoting marks (">")
-    pub fn highlight_text(&mut self, text: &str) -> String {
-        self.highlight_quoting_marks(text)
+    pub fn highlight_text(&mut self, span: &Span) -> String {
+        let theme = &self.theme;
+        paint_span_lines(theme, span, |line, rest| {
+            paint_quoted_rest(theme, line, rest, &Style::default())
+        })
     }
 
     /// Highlight a diffheader
-    pub fn highlight_diffh(&mut self, diffh: &str) -> String {
-        self.highlight_quoting_marks(diffh)
+    pub fn highlight_diffh(&mut self, span: &Span) -> String {
+        let theme = &self.theme;
+        paint_span_lines(theme, span, |line, rest| {
+            paint_quoted_rest(theme, line, rest, &theme.diff.header)
+        })
     }
 
-    /// Highlight an individual hunk of text
-    pub fn highlight_diffm(&mut self, diffm: &str) -> String {
-        self.highlight_quoting_marks(diffm)
+    /// Highlight the extended header lines of a diff
+    ///
+    /// Every line is painted according to its `MetadataKind`, so file
+    /// creations, deletions, mode changes, renames, and binary files stand
+    /// out from the routine `index` lines.
+    pub fn highlight_diffm(&mut self, span: &Span) -> String {
+        let theme = &self.theme;
+        paint_span_lines(theme, span, |line, rest| {
+            let style = match line {
+                Line::DiffMetadata { kind, .. } => metadata_style(theme, kind),
+                _ => None,
+            };
+            match style {
+                Some(style) => style.paint(rest),
+                None => rest.to_string(),
+            }
+        })
+    }
+
+    /// Fold a `GIT binary patch` block into a one-line summary
+    ///
+    /// The base85 data is of no use to a reader, so only the encoding
+    /// (`literal` or `delta`) and the inflated size of the forward patch,
+    /// and of the reverse patch if there is one, are shown.
+    pub fn highlight_binary(&mut self, span: &Span) -> String {
+        let lines = span.get_lines();
+        let (prefix, _) = lines.first().map(Line::split_quote).unwrap_or_default();
+
+        let blocks: Vec<String> = lines
+            .iter()
+            .filter_map(|line| match line {
+                Line::BinaryPatch {
+                    kind: BinaryKind::Literal(size),
+                    ..
+                } => Some(format!("literal {size} bytes")),
+                Line::BinaryPatch {
+                    kind: BinaryKind::Delta(size),
+                    ..
+                } => Some(format!("delta {size} bytes")),
+                _ => None,
+            })
+            .collect();
+        let summary = match blocks.as_slice() {
+            [] => String::from("binary data"),
+            [forward] => forward.clone(),
+            [forward, reverse, ..] => format!("{forward}, reverse {reverse}"),
+        };
+
+        format!(
+            "{}{}",
+            paint_quote_line(&self.theme, prefix),
+            self.theme
+                .message
+                .binary_fold
+                .paint(&format!("[{summary}, {} lines folded]", lines.len()))
+        )
     }
 
     /// Highlight a hunk header
-    pub fn highlight_hunkh(&mut self, hunkh: &str) -> String {
-        self.highlight_quoting_marks(hunkh)
-    }
-}
-
-/// Convert highlight class ID to ANSI color
-pub fn ansi_for_class(class: usize) -> &'static str {
-    match class {
-        0 => "\x1b[1;34m", // function
-        1 => "\x1b[1;36m", // type
-        2 => "\x1b[0;32m", // string
-        3 => "\x1b[1;35m", // keyword
-        4 => "\x1b[0;36m", // number
-        5 => "\x1b[0;90m", // comment
-        6 => "\x1b[1;33m", // constant
-        7 => "\x1b[1;31m", // operator
-        8 => "\x1b[0m",    // default
-        _ => "\x1b[0m",
+    ///
+    /// The header of a hunk damaged by a mail client is painted as a warning,
+    /// as some of the damage (e.g. stripped empty context lines) is invisible.
+    pub fn highlight_hunkh(&mut self, span: &Span) -> String {
+        let theme = &self.theme;
+        paint_span_lines(theme, span, |line, rest| match line {
+            Line::HunkHeader {
+                damage: Some(_), ..
+            } => theme.message.warning.paint(rest),
+            _ => theme.diff.hunk_header.paint(rest),
+        })
+    }
+
+    /// Highlight a separator (`---` or a scissors line)
+    pub fn highlight_sep(&mut self, span: &Span) -> String {
+        let theme = &self.theme;
+        paint_span_lines(theme, span, |_, rest| theme.message.separator.paint(rest))
+    }
+
+    /// Highlight a diffstat
+    ///
+    /// The plus-minus bars of the file entries and the insertion and deletion
+    /// counts of the summary are painted like added and removed lines.
+    pub fn highlight_stat(&mut self, span: &Span) -> String {
+        let diff = &self.theme.diff;
+        paint_span_lines(&self.theme, span, |_, rest| {
+            if let Some((path, bar)) = rest.rsplit_once(" | ") {
+                let mut out = format!("{path} | ");
+                for ch in bar.chars() {
+                    match ch {
+                        '+' => out.push_str(&diff.add.paint("+")),
+                        '-' => out.push_str(&diff.remove.paint("-")),
+                        _ => out.push(ch),
+                    }
+                }
+                out
+            } else {
+                rest.split(", ")
+                    .map(|part| {
+                        if part.contains("insertion") {
+                            diff.add.paint(part)
+                        } else if part.contains("deletion") {
+                            diff.remove.paint(part)
+                        } else {
+                            part.to_string()
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        })
+    }
+
+    /// Highlight a signature (everything after the `-- ` delimiter)
+    pub fn highlight_sig(&mut self, span: &Span) -> String {
+        let theme = &self.theme;
+        paint_span_lines(theme, span, |_, rest| theme.message.signature.paint(rest))
+    }
+}
+
+/// Paint each line of a span with `paint`, which gets the line without its quote prefix
+///
+/// The quote prefix is painted as usual, and the original line terminators are kept.
+/// Empty remainders are left alone, so no escape sequences are emitted for them.
+fn paint_span_lines(theme: &Theme, span: &Span, paint: impl Fn(&Line, &str) -> String) -> String {
+    let lines = span.get_lines();
+    let mut output = String::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let (prefix, rest) = line.split_quote();
+        output.push_str(&paint_quote_line(theme, prefix));
+        if !rest.is_empty() {
+            output.push_str(&paint(line, rest));
+        }
+
+        if i + 1 < lines.len() {
+            output.push_str(line_terminator(line));
+        }
+    }
+    output
+}
+
+/// Get the byte ranges of the quote marks of a quote prefix, one per depth
+///
+/// Stacked marks (`>>`) are one depth each, while an attribution
+/// (`Linus>`) is a single one.
+fn quote_marks(prefix: &str) -> Vec<Range<usize>> {
+    let mut marks = Vec::new();
+    let mut offset = 0;
+
+    for word in prefix.split([' ', '\t']) {
+        if word.chars().all(|c| c == '>' || c == '|') {
+            marks.extend((0..word.len()).map(|i| offset + i..offset + i + 1));
+        } else {
+            marks.push(offset..offset + word.len());
+        }
+        offset += word.len() + 1;
+    }
+    marks
+}
+
+/// Paint the quote marks of a quote prefix (see `QuoteProfile::split`), each in the style of its depth
+///
+/// The whitespace in between the marks is left alone.
+#[cfg(not(feature = "quote-paint-full"))]
+fn paint_quote_line(theme: &Theme, prefix: &str) -> String {
+    let mut out = String::with_capacity(prefix.len() + 8);
+    let mut last_end = 0;
+
+    for (depth, mark) in quote_marks(prefix).into_iter().enumerate() {
+        out.push_str(&prefix[last_end..mark.start]);
+        out.push_str(&theme.quote_style(depth + 1).paint(&prefix[mark.clone()]));
+        last_end = mark.end;
+    }
+    out.push_str(&prefix[last_end..]);
+    out
+}
+
+/// Paint the full quote prefix, in the style of its depth
+#[cfg(feature = "quote-paint-full")]
+fn paint_quote_line(theme: &Theme, prefix: &str) -> String {
+    if prefix.is_empty() {
+        return String::new();
+    }
+    theme.quote_style(quote_marks(prefix).len()).paint(prefix)
+}
+
+/// Paint the rest of a quoted line with `style`, or like its quote prefix if full quotes are painted
+fn paint_quoted_rest(theme: &Theme, line: &Line, rest: &str, style: &Style) -> String {
+    let depth = line.get_quoting_layer();
+    if cfg!(feature = "quote-paint-full") && depth > 0 {
+        theme.quote_style(depth).paint(rest)
+    } else {
+        style.paint(rest)
+    }
+}
+
+/// Paint the diff marker columns (`+`, `-`, or ` `) of a code line
+fn paint_marker(theme: &Theme, marker: &str) -> String {
+    /* Combined diffs have one column per parent, each one is painted on its own */
+    marker
+        .chars()
+        .map(|c| match c {
+            '+' => theme.diff.add.paint("+"),
+            '-' => theme.diff.remove.paint("-"),
+            ' ' => theme.diff.context.paint(" "),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+/// Get the style of the side of the change a code line is on
+///
+/// A line of a combined diff is removed if any parent removes it, and
+/// added if any parent lacks it.
+fn line_style<'a>(theme: &'a Theme, kind: &CodeKind) -> &'a Style {
+    match kind {
+        CodeKind::Add => &theme.diff.add,
+        CodeKind::Remove => &theme.diff.remove,
+        CodeKind::Context | CodeKind::NoNewline(_) => &theme.diff.context,
+        CodeKind::Combined(markers) if markers.iter().any(|m| matches!(m, CodeKind::Remove)) => {
+            &theme.diff.remove
+        }
+        CodeKind::Combined(markers) if markers.iter().any(|m| matches!(m, CodeKind::Add)) => {
+            &theme.diff.add
+        }
+        CodeKind::Combined(_) => &theme.diff.context,
+    }
+}
+
+/// Build the pre-image and the post-image of the code lines of a hunk
+///
+/// Returns both images, and where each line is painted from: the image and
+/// the offset the line starts at in it, or `None` if the line is not code.
+/// Context lines are in both images, but they are painted from the post-image.
+fn build_images(lines: &[Line]) -> (String, String, Vec<Option<(Image, usize)>>) {
+    let mut pre = String::new();
+    let mut post = String::new();
+    let mut placements = Vec::with_capacity(lines.len());
+    for line in lines {
+        let (_, _, code) = line.split_code().unwrap_or_default();
+        let Line::Code { kind, .. } = line else {
+            continue;
+        };
+        if kind.in_new() {
+            placements.push(Some((Image::Post, post.len())));
+            push_line(&mut post, code);
+        } else if kind.in_old() {
+            placements.push(Some((Image::Pre, pre.len())));
+        } else {
+            /* The no newline marker is not code */
+            placements.push(None);
+        }
+        if kind.in_old() {
+            push_line(&mut pre, code);
+        }
+    }
+    (pre, post, placements)
+}
+
+/// Split the source into chunks that contain no ERROR nodes
+///
+/// Walks the tree from `node` and collects the byte ranges of the largest
+/// subtrees free of ERROR (and MISSING) nodes. Chunks only separated by
+/// whitespace are merged. The returned ranges are sorted and never overlap.
+pub fn collect_non_error_chunks(
+    lang: &str,
+    node: tree_sitter::Node,
+    src: &str,
+) -> Vec<(usize, usize)> {
+    fn walk(lang: &str, node: tree_sitter::Node, chunks: &mut Vec<(usize, usize)>) {
+        if !has_real_error(lang, node) {
+            if node.start_byte() < node.end_byte() {
+                chunks.push((node.start_byte(), node.end_byte()));
+            }
+            return;
+        }
+        /* Leaf ERROR tokens are invalid on their own, but their siblings may not be */
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            walk(lang, child, chunks);
+        }
+    }
+
+    let mut chunks = Vec::new();
+    walk(lang, node, &mut chunks);
+
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(chunks.len());
+    for (start, end) in chunks {
+        match merged.last_mut() {
+            Some(last) if src[last.1..start].trim().is_empty() => last.1 = end,
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Check if a tree has errors, other than the ones kernel macros cause in C
+///
+/// The C grammar does not know the macros, but two kinds of them are
+/// common enough to be worth an exception. An ERROR made of a lone
+/// identifier is an annotation between a type and a declarator
+/// (`int __init foo(void)`, `char __user *buf`), and a `;` missing between
+/// a call and a block is an iterator macro (`list_for_each_entry(...) {`).
+/// The kernel highlight layer paints both.
+fn has_real_error(lang: &str, node: tree_sitter::Node) -> bool {
+    if !node.has_error() {
+        return false;
+    }
+    if lang != "c" {
+        return true;
+    }
+
+    if node.is_error() {
+        return !(node.child_count() == 1
+            && node.child(0).is_some_and(|c| c.kind() == "identifier"));
+    }
+    if node.is_missing() {
+        let statement = node.parent().filter(|p| p.kind() == "expression_statement");
+        return !statement.is_some_and(|s| {
+            s.named_child(0)
+                .is_some_and(|c| c.kind() == "call_expression")
+                && s.next_sibling()
+                    .is_some_and(|n| n.kind() == "compound_statement")
+        });
+    }
+
+    let mut cursor = node.walk();
+    node.children(&mut cursor)
+        .any(|child| has_real_error(lang, child))
+}
+
+/// Get the synthetic wrappers to try when `code` does not parse on its own
+///
+/// Each wrapper is a `(prefix, suffix)` pair that puts the code into a context
+/// where it is valid, e.g. the body of a function. Unbalanced braces are
+/// compensated, so a hunk ending with the closing brace of a function still
+/// parses as a function body. A block comment makes any text parse, so it is
+/// only tried if the code looks like the inside of one (see `in_block_comment`).
+fn synthetic_wrappers(lang: &str, code: &str) -> Vec<(String, String)> {
+    match lang {
+        "c" => {
+            let (prefix, suffix) = balanced_body("void __kmailight(void)", "", code);
+            let mut wrappers = vec![
+                (prefix, suffix),
+                ("struct __kmailight {\n".to_string(), "\n};\n".to_string()),
+            ];
+            if in_block_comment(code) {
+                wrappers.push(("/*\n".to_string(), "\n*/\n".to_string()));
+            }
+            wrappers
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Check if `code` starts inside a block comment, with a leading ` * ` line
+#[inline]
+fn in_block_comment(code: &str) -> bool {
+    code.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .is_some_and(|line| line == "*" || line.starts_with("* "))
+}
+
+/// Get a wrapper that reconstructs the function or type the hunk is in
+///
+/// Git puts the signature of the enclosing function (or the head of the
+/// enclosing struct, enum, initializer, etc.) after the hunk ranges. Wrapping
+/// the hunk in it tells tree-sitter that it is parsing a function body.
+fn context_wrapper(lang: &str, context: &str, code: &str) -> Option<(String, String)> {
+    if lang != "c" || context.is_empty() {
+        return None;
+    }
+
+    if let Some(head) = context.strip_suffix('{') {
+        /* Aggregates and initializers need a `;` after the closing brace */
+        let terminator = if head.contains('(') && !head.contains('=') {
+            ""
+        } else {
+            ";"
+        };
+        Some(balanced_body(head, terminator, code))
+    } else if context.contains('(') && !context.ends_with(';') {
+        Some(balanced_body(context, "", code))
+    } else {
+        None
+    }
+}
+
+/// Wrap `code` in a body opened after `head` and closed by `}` and `terminator`
+///
+/// The braces are balanced against `code`: the body is opened as many
+/// times as `code` closes braces it never opened (at least once), and
+/// closed again for every brace left open at the end.
+fn balanced_body(head: &str, terminator: &str, code: &str) -> (String, String) {
+    let (missing_open, missing_close) = unbalanced_braces(code);
+    let opens = missing_open.max(1);
+    let closes = opens - missing_open + missing_close;
+
+    let terminator = if closes > 0 { terminator } else { "" };
+
+    let prefix = format!("{head}\n{}", "{\n".repeat(opens));
+    let suffix = format!("\n{}{terminator}\n", "}\n".repeat(closes).trim_end());
+    (prefix, suffix)
+}
+
+/// Get the length of the block comment tail `code` starts in, if any
+///
+/// A hunk may start in the middle of a block comment, which is detected
+/// by a closing `*/` that appears before any opening `/*`.
+fn comment_tail_len(lang: &str, code: &str) -> Option<usize> {
+    if lang != "c" {
+        return None;
+    }
+    let close = code.find("*/")?;
+    match code.find("/*") {
+        Some(open) if open < close => None,
+        _ => Some(close + 2),
+    }
+}
+
+/// Count the `{` missing before and the `}` missing after `code` to balance it
+///
+/// This is naive: braces in strings and comments are counted as well.
+fn unbalanced_braces(code: &str) -> (usize, usize) {
+    let mut depth = 0isize;
+    let mut min_depth = 0isize;
+    for b in code.bytes() {
+        match b {
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                min_depth = min_depth.min(depth);
+            }
+            _ => {}
+        }
+    }
+    ((-min_depth) as usize, (depth - min_depth) as usize)
+}
+
+/// Keep only the parts of the regions that fall into `[start, end)`
+fn clip_regions(regions: &[Region], start: usize, end: usize) -> Vec<Region> {
+    regions
+        .iter()
+        .filter_map(|r| {
+            let from = r.start.max(start);
+            let to = r.end.min(end);
+            (from < to).then_some(Region {
+                start: from,
+                end: to,
+                class: r.class,
+            })
+        })
+        .collect()
+}
+
+/// Move the regions `by` bytes forward
+fn shift_regions(regions: Vec<Region>, by: usize) -> Vec<Region> {
+    regions
+        .into_iter()
+        .map(|r| Region {
+            start: r.start + by,
+            end: r.end + by,
+            class: r.class,
+        })
+        .collect()
+}
+
+/// A single unhighlighted region spanning `[start, end)`
+#[inline]
+fn plain_region(start: usize, end: usize) -> Vec<Region> {
+    vec![Region {
+        start,
+        end,
+        class: None,
+    }]
+}
+
+/// Append a line of code to a virtual source file
+#[inline]
+fn push_line(source: &mut String, code: &str) {
+    source.push_str(code);
+    source.push('\n');
+}
+
+/// Paint `range` of `source` using the highlighted regions
+///
+/// Every region is painted on top of `background`, the escape sequence of
+/// the background of the line (see `DiffStyles`).
+fn paint_regions(
+    out: &mut String,
+    source: &str,
+    regions: &[Region],
+    styles: &[String],
+    background: &str,
+    range: Range<usize>,
+) {
+    for region in regions {
+        let from = region.start.max(range.start);
+        let to = region.end.min(range.end);
+        if from >= to {
+            continue;
+        }
+
+        let style = region.class.map_or("", |class| styles[class].as_str());
+        if style.is_empty() && background.is_empty() {
+            out.push_str(&source[from..to]);
+        } else {
+            out.push_str(background);
+            out.push_str(style);
+            out.push_str(&source[from..to]);
+            out.push_str(RESET);
+        }
+    }
+}
+
+/// Get the style of an extended header line of a diff
+fn metadata_style<'a>(theme: &'a Theme, kind: &MetadataKind) -> Option<&'a Style> {
+    let styles = &theme.metadata;
+    match kind {
+        MetadataKind::Index { .. } => Some(&styles.index),
+        MetadataKind::NewFileMode(_) => Some(&styles.new_file),
+        MetadataKind::DeletedFileMode(_) => Some(&styles.deleted_file),
+        MetadataKind::OldMode(_) | MetadataKind::NewMode(_) => Some(&styles.mode),
+        MetadataKind::Similarity(_)
+        | MetadataKind::Dissimilarity(_)
+        | MetadataKind::RenameFrom
+        | MetadataKind::RenameTo
+        | MetadataKind::CopyFrom
+        | MetadataKind::CopyTo => Some(&styles.rename),
+        MetadataKind::OldFile | MetadataKind::NewFile => Some(&styles.file),
+        MetadataKind::Binary | MetadataKind::GitBinaryPatch => Some(&styles.binary),
+        MetadataKind::Unknown => None,
+    }
+}
+
+/// Get the class of a capture name, or of the closest parent name that has one
+fn class_of(capture: &str) -> Option<usize> {
+    let mut name = capture;
+    loop {
+        if let Some(class) = HIGHLIGHT_NAMES.iter().position(|&n| n == name) {
+            return Some(class);
+        }
+        name = &name[..name.rfind('.')?];
+    }
+}
+
+/// Find the config of an injected language, by id or by extension (see `language::by_name`)
+fn injection_config<'a>(
+    configs: &'a HashMap<&'static str, HighlightConfiguration>,
+    name: &str,
+) -> Option<&'a HighlightConfiguration> {
+    configs
+        .get(name)
+        .or_else(|| configs.get(language::by_name(name)?.id))
+}
+
+/// Lay the sorted, non-overlapping `overlay` regions over `regions`
+///
+/// The regions partially covered by an overlay are split around it.
+fn overlay_regions(regions: &[Region], overlay: &[Region]) -> Vec<Region> {
+    let mut out = Vec::with_capacity(regions.len() + 2 * overlay.len());
+    let mut overlay = overlay.iter().peekable();
+
+    for region in regions {
+        let mut start = region.start;
+        while start < region.end {
+            while overlay.next_if(|o| o.end <= start).is_some() {}
+            let end = match overlay.peek() {
+                Some(o) if o.start <= start => {
+                    let end = o.end.min(region.end);
+                    out.push(Region {
+                        start,
+                        end,
+                        class: o.class,
+                    });
+                    start = end;
+                    continue;
+                }
+                Some(o) => o.start.min(region.end),
+                None => region.end,
+            };
+            out.push(Region {
+                start,
+                end,
+                class: region.class,
+            });
+            start = end;
+        }
+    }
+    out
+}
+
+/// Get the original line terminator of a line
+fn line_terminator(line: &Line) -> &'static str {
+    if line.get_length() - line.get_raw().len() == 2 {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::quote::QuoteProfile;
+    use crate::parser::span::build_spans;
+
+    /// Remove the escape sequences from painted output
+    fn unpaint(painted: &str) -> String {
+        let mut out = String::new();
+        let mut rest = painted;
+        while let Some(start) = rest.find("\x1b[") {
+            out.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find('m')
+                .map_or(rest.len(), |m| start + m + 1);
+            rest = &rest[end..];
+        }
+        out.push_str(rest);
+        out
+    }
+
+    /// Highlight the first span of code of `source`
+    fn highlight_hunk(source: &str) -> String {
+        let lines = Line::parse_lines(source, QuoteProfile::default());
+        let spans = build_spans(&lines);
+        let span = spans
+            .iter()
+            .find(|s| matches!(s, Span::Code { .. }))
+            .expect("no code span");
+        HighlighterEngine::new(Theme::default()).highlight_code(span)
+    }
+
+    #[test]
+    fn quote_prefixes_and_markers_are_kept() {
+        let source = "> diff --git a/f.c b/f.c\n\
+                      > @@ -1,2 +1,2 @@\n\
+                      >  int a;\n\
+                      > -return 0;\n\
+                      > +return 1;\n";
+        let painted = highlight_hunk(source);
+        assert_eq!(unpaint(&painted), ">  int a;\n> -return 0;\n> +return 1;");
+    }
+
+    #[test]
+    fn code_is_highlighted_without_its_prefix() {
+        let source = ">> diff --git a/f.c b/f.c\n\
+                      >> @@ -1 +1 @@\n\
+                      >> -return 0;\n\
+                      >> +return 1;\n";
+        let painted = highlight_hunk(source);
+        /* Both lines start with the keyword, right after the painted marker */
+        assert_eq!(painted.matches("mreturn").count(), 2, "{painted:?}");
+    }
+
+    /// Get the code lines of `source`
+    fn code_lines(source: &str) -> Vec<Line<'_>> {
+        Line::parse_lines(source, QuoteProfile::default())
+            .into_iter()
+            .filter(|line| matches!(line, Line::Code { .. }))
+            .collect()
+    }
+
+    #[test]
+    fn images_of_a_hunk() {
+        let source = "diff --git a/f.c b/f.c\n\
+                      @@ -1,3 +1,3 @@\n\
+                      \x20a;\n\
+                      -b;\n\
+                      +c;\n\
+                      \x20d;\n";
+        let (pre, post, placements) = build_images(&code_lines(source));
+        assert_eq!(pre, "a;\nb;\nd;\n");
+        assert_eq!(post, "a;\nc;\nd;\n");
+        assert_eq!(
+            placements,
+            [
+                Some((Image::Post, 0)),
+                Some((Image::Pre, 3)),
+                Some((Image::Post, 3)),
+                Some((Image::Post, 6)),
+            ]
+        );
+    }
+
+    #[test]
+    fn images_of_a_combined_diff() {
+        let source = "diff --cc f.c\n\
+                      @@@ -1,2 -1,2 +1,2 @@@\n\
+                      - a;\n\
+                      \x20-b;\n\
+                      ++c;\n\
+                      \x20 d;\n";
+        let (pre, post, _) = build_images(&code_lines(source));
+        assert_eq!(pre, "a;\nb;\nd;\n");
+        assert_eq!(post, "c;\nd;\n");
+    }
+
+    #[test]
+    fn no_newline_marker_is_in_neither_image() {
+        let source = "diff --git a/f.c b/f.c\n\
+                      @@ -1 +1 @@\n\
+                      -a;\n\
+                      \\ No newline at end of file\n\
+                      +a;\n";
+        let (pre, post, placements) = build_images(&code_lines(source));
+        assert_eq!((pre.as_str(), post.as_str()), ("a;\n", "a;\n"));
+        assert_eq!(placements[1], None);
+    }
+
+    /// Get the capture name of the region of `code` that starts with `token`
+    fn capture_at(regions: &[Region], code: &str, token: &str) -> Option<&'static str> {
+        let start = code.find(token)?;
+        let region = regions.iter().find(|r| r.start <= start && start < r.end)?;
+        region.class.map(|class| HIGHLIGHT_NAMES[class])
+    }
+
+    #[test]
+    fn comment_tail() {
+        assert_eq!(comment_tail_len("c", " * end */\nint a;\n"), Some(9));
+        assert_eq!(comment_tail_len("c", "/* a */ int b; /* c */"), None);
+        assert_eq!(comment_tail_len("c", "int a;\n"), None);
+        assert_eq!(comment_tail_len("rust", " * end */\n"), None);
+    }
+
+    #[test]
+    fn block_comment_lines() {
+        assert!(in_block_comment("\n * Frobnicate the widget.\n *\n"));
+        assert!(in_block_comment("\t*\n"));
+        assert!(!in_block_comment("\t*p = 0;\n"));
+        assert!(!in_block_comment("int a;\n * b;\n"));
+    }
+
+    #[test]
+    fn unbalanced_brace_counts() {
+        assert_eq!(unbalanced_braces("\treturn 0;\n}\n"), (1, 0));
+        assert_eq!(unbalanced_braces("if (a) {\n"), (0, 1));
+        assert_eq!(unbalanced_braces("}\nelse {\n"), (1, 1));
+        assert_eq!(unbalanced_braces("{ }"), (0, 0));
+    }
+
+    #[test]
+    fn chunks_skip_error_nodes() {
+        let code = "\t\t arg2, arg3);\n\tfoo(1);\n";
+        let mut engine = HighlighterEngine::new(Theme::default());
+        let tree = engine.parse("c", code).unwrap();
+        let chunks = collect_non_error_chunks("c", tree.root_node(), code);
+
+        let call = code.find("foo").unwrap();
+        assert!(
+            chunks
+                .iter()
+                .any(|&(start, end)| start <= call && call + "foo(1);".len() <= end),
+            "{chunks:?}"
+        );
+        assert!(chunks.windows(2).all(|w| w[0].1 <= w[1].0));
+    }
+
+    #[test]
+    fn partial_code_falls_back_to_chunks() {
+        let code = "\t\t arg2, arg3);\n\tfoo(1);\n\tbar(2);\n";
+        let mut engine = HighlighterEngine::new(Theme::default());
+        let regions = engine.collect_regions("c", "", code);
+
+        assert_eq!(capture_at(&regions, code, "foo"), Some("function"));
+        assert_eq!(capture_at(&regions, code, "bar"), Some("function"));
+        assert!(
+            regions
+                .iter()
+                .all(|r| r.class.is_none_or(|c| HIGHLIGHT_NAMES[c] != "comment"))
+        );
+    }
+
+    #[test]
+    fn hunk_inside_a_comment() {
+        let code = " * Frobnicate the widget.\n *\n * Return: 0 on success\n";
+        let mut engine = HighlighterEngine::new(Theme::default());
+        let regions = engine.collect_regions("c", "", code);
+
+        assert_eq!(capture_at(&regions, code, "Frobnicate"), Some("comment"));
+        assert_eq!(capture_at(&regions, code, "Return"), Some("comment"));
+    }
+
+    #[test]
+    fn hunk_starting_in_a_comment() {
+        let code = " * the end of a comment\n */\nint a;\n";
+        let mut engine = HighlighterEngine::new(Theme::default());
+        let regions = engine.collect_regions("c", "", code);
+
+        assert_eq!(capture_at(&regions, code, "the end"), Some("comment"));
+        assert_eq!(capture_at(&regions, code, "int"), Some("type"));
+    }
+
+    fn wrapper(prefix: &str, suffix: &str) -> Option<(String, String)> {
+        Some((prefix.to_string(), suffix.to_string()))
+    }
+
+    #[test]
+    fn function_context_wrapper() {
+        let context = "static int foo(struct bar *b)";
+        assert_eq!(
+            context_wrapper("c", context, "\treturn 0;\n"),
+            wrapper("static int foo(struct bar *b)\n{\n", "\n}\n")
+        );
+        /* The hunk closes the function itself */
+        assert_eq!(
+            context_wrapper("c", context, "\treturn 0;\n}\n"),
+            wrapper("static int foo(struct bar *b)\n{\n", "\n\n")
+        );
+    }
+
+    #[test]
+    fn aggregate_context_wrapper() {
+        assert_eq!(
+            context_wrapper("c", "struct foo {", "\tint a;\n"),
+            wrapper("struct foo \n{\n", "\n};\n")
+        );
+        assert_eq!(
+            context_wrapper(
+                "c",
+                "static const struct ops foo_ops = {",
+                "\t.open = foo_open,\n"
+            ),
+            wrapper("static const struct ops foo_ops = \n{\n", "\n};\n")
+        );
+    }
+
+    #[test]
+    fn no_context_wrapper() {
+        assert_eq!(context_wrapper("c", "", "a;\n"), None);
+        assert_eq!(context_wrapper("c", "int foo(void);", "a;\n"), None);
+        assert_eq!(context_wrapper("c", "#define FOO", "a;\n"), None);
+        assert_eq!(context_wrapper("rust", "fn foo() {", "a;\n"), None);
+    }
+
+    #[test]
+    fn statements_are_parsed_in_their_function() {
+        let code = "\tstruct bar *b = a->b;\n\n\treturn b->c;\n";
+        let mut engine = HighlighterEngine::new(Theme::default());
+        let regions = engine.collect_regions("c", "static int foo(struct baz *a)", code);
+
+        assert_eq!(capture_at(&regions, code, "bar"), Some("type"));
+        assert_eq!(capture_at(&regions, code, "return"), Some("keyword"));
+    }
+
+    #[test]
+    fn subject_tags_are_painted_from_the_tag() {
+        let theme = Theme::default();
+        let painted = HighlighterEngine::new(theme.clone())
+            .highlight_headers("Subject: [PATCH v2 3/7] mm: fix a leak\nTo: someone\n");
+        let expected = format!(
+            "{} {} mm: fix a leak\n{} someone\n",
+            theme.headers.name.paint("Subject:"),
+            theme.headers.subject_tags.paint("[PATCH v2 3/7]"),
+            theme.headers.name.paint("To:"),
+        );
+        assert_eq!(painted, expected);
+    }
+
+    fn bounds(regions: &[Region]) -> Vec<(usize, usize, Option<usize>)> {
+        regions.iter().map(|r| (r.start, r.end, r.class)).collect()
+    }
+
+    #[test]
+    fn overlay_splits_the_regions_it_covers() {
+        let region = |start, end, class| Region { start, end, class };
+        let regions = [region(0, 10, None), region(10, 20, Some(1))];
+        let overlay = [
+            region(2, 4, Some(5)),
+            region(8, 12, Some(6)),
+            region(25, 30, Some(7)),
+        ];
+        assert_eq!(
+            bounds(&overlay_regions(&regions, &overlay)),
+            [
+                (0, 2, None),
+                (2, 4, Some(5)),
+                (4, 8, None),
+                (8, 10, Some(6)),
+                (10, 12, Some(6)),
+                (12, 20, Some(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn fallback_injections_are_laid_over_the_host() {
+        let code = "\tpr_info(\"%s: %d\\n\", name, err);\n\tasm volatile(\"nop\");\n";
+        let mut engine = HighlighterEngine::new(Theme::default());
+        let regions = engine.collect_regions("c", "", code);
+
+        assert_eq!(capture_at(&regions, code, "%s"), Some("string.special"));
+        assert_eq!(capture_at(&regions, code, ": "), Some("string"));
+        assert_eq!(capture_at(&regions, code, "nop"), Some("keyword"));
+    }
+
+    #[test]
+    fn capture_names_fall_back_to_their_parents() {
+        let name = |capture| class_of(capture).map(|class| HIGHLIGHT_NAMES[class]);
+        assert_eq!(name("function.builtin"), Some("function.builtin"));
+        assert_eq!(name("function.builtin.static"), Some("function.builtin"));
+        assert_eq!(
+            name("keyword.conditional.ternary"),
+            Some("keyword.conditional")
+        );
+        assert_eq!(
+            name("constant.character.escape"),
+            Some("constant.character.escape")
+        );
+        assert_eq!(name("spell"), None);
+        assert_eq!(name("nospell.comment"), None);
+    }
+
+    #[test]
+    fn damaged_lines_are_painted_as_warnings() {
+        let theme = Theme::default();
+        let source = "diff --git a/f.c b/f.c\n\
+                      @@ -1,2 +1,2 @@\n\
+                      \u{a0}int a;\n\
+                      -int b;\n\
+                      +int c;\n";
+        let painted = highlight_hunk(source);
+        assert!(painted.contains(&theme.message.warning.paint("\u{a0}int a;")));
+        assert!(!painted.contains(&theme.message.warning.paint("int c;")));
     }
 }
@@ -0,0 +1,334 @@
+/// Injected languages painted without a grammar (see `fallback`)
+pub const FALLBACKS: &[&str] = &["asm", "printf", "kernel-doc"];
+
+/// Highlight the text of an injected language that has no grammar
+///
+/// Returns the highlighted byte ranges of `text` with their capture names,
+/// or `None` if there is no fallback for the language. A grammar loaded for
+/// one of these languages takes precedence over its fallback.
+pub fn fallback(language: &str, text: &str) -> Option<Vec<(usize, usize, &'static str)>> {
+    match language {
+        "asm" => Some(gnu_asm(text)),
+        "printf" => Some(format_specifiers(text)),
+        "kernel-doc" => Some(kernel_doc(text)),
+        _ => None,
+    }
+}
+
+/// Highlight the GNU assembly in the string literals of an `asm` statement
+///
+/// Only the string contents are looked at, so macros pasted between them
+/// (`"mov " __stringify(X) "\n"`) are left alone. The first word of each
+/// instruction is the mnemonic (or a directive, or a label if a colon
+/// follows), `%0` and `%[name]` are operands, `%%eax` is a register, and
+/// `$1` is an immediate.
+fn gnu_asm(text: &str) -> Vec<(usize, usize, &'static str)> {
+    let bytes = text.as_bytes();
+    let mut spans = Vec::new();
+    let mut in_string = false;
+    let mut at_mnemonic = true;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        match bytes[i] {
+            b'"' => {
+                in_string = !in_string;
+                i += 1;
+            }
+            _ if !in_string => i += 1,
+            b'\\' => {
+                /* `\n` ends an instruction, the other escapes are whitespace */
+                if bytes.get(i + 1) == Some(&b'n') {
+                    at_mnemonic = true;
+                }
+                i += 2;
+            }
+            b';' => {
+                at_mnemonic = true;
+                i += 1;
+            }
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'"' && !bytes[i..].starts_with(b"\\n") {
+                    i += 1;
+                }
+                spans.push((start, i, "comment"));
+            }
+            b'%' => {
+                let register = bytes.get(i + 1) == Some(&b'%');
+                i += if register { 2 } else { 1 };
+                /* Operand modifiers (`%w0`, `%k[x]`) */
+                if !register
+                    && bytes.get(i).is_some_and(u8::is_ascii_alphabetic)
+                    && matches!(bytes.get(i + 1), Some(b'0'..=b'9' | b'['))
+                {
+                    i += 1;
+                }
+                let operand = matches!(bytes.get(i), Some(b'0'..=b'9' | b'['));
+                if bytes.get(i) == Some(&b'[') {
+                    i += bytes[i..]
+                        .iter()
+                        .position(|&b| b == b']')
+                        .map_or(1, |end| end + 1);
+                } else {
+                    i = word_end(bytes, i);
+                }
+                if i > start + 1 {
+                    let capture = if operand {
+                        "variable.parameter"
+                    } else {
+                        "variable.builtin"
+                    };
+                    spans.push((start, i, capture));
+                }
+            }
+            b'$' => {
+                i = word_end(bytes, i + 1);
+                spans.push((start, i, "number"));
+            }
+            b'0'..=b'9' => {
+                i = word_end(bytes, i);
+                if at_mnemonic && bytes.get(i) == Some(&b':') {
+                    i += 1;
+                    spans.push((start, i, "label"));
+                } else {
+                    spans.push((start, i, "number"));
+                }
+            }
+            b if b == b'.' || b == b'_' || b.is_ascii_alphabetic() => {
+                i = word_end(bytes, i + 1);
+                if bytes.get(i) == Some(&b':') {
+                    i += 1;
+                    spans.push((start, i, "label"));
+                } else if at_mnemonic {
+                    at_mnemonic = false;
+                    spans.push((start, i, "keyword"));
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    spans
+}
+
+/// Get the end of the word (identifier or number) starting at `i`
+fn word_end(bytes: &[u8], mut i: usize) -> usize {
+    while bytes
+        .get(i)
+        .is_some_and(|&b| b.is_ascii_alphanumeric() || b == b'_' || b == b'.')
+    {
+        i += 1;
+    }
+    i
+}
+
+/// Find the conversion specifiers of a printf-style format string
+///
+/// This covers the standard ones (`%-08lx`, `%.*s`, `%%`) and the kernel
+/// pointer extensions (`%pOF`, `%pI4`, `%*ph`), which take every
+/// alphanumeric character after the `p`, as `vsprintf` does.
+fn format_specifiers(text: &str) -> Vec<(usize, usize, &'static str)> {
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while let Some(pos) = text[i..].find('%') {
+        let start = i + pos;
+        match specifier_len(&text.as_bytes()[start + 1..]) {
+            Some(len) => {
+                i = start + 1 + len;
+                spans.push((start, i, "string.special"));
+            }
+            None => i = start + 1,
+        }
+    }
+    spans
+}
+
+/// Get the length of the conversion specifier after a `%`, if it is a valid one
+fn specifier_len(spec: &[u8]) -> Option<usize> {
+    if spec.first() == Some(&b'%') {
+        return Some(1);
+    }
+
+    let mut i = 0;
+    while matches!(spec.get(i), Some(b'-' | b'+' | b' ' | b'#' | b'0')) {
+        i += 1;
+    }
+    /* Width and precision */
+    let skip_number = |mut i: usize| {
+        if spec.get(i) == Some(&b'*') {
+            return i + 1;
+        }
+        while spec.get(i).is_some_and(u8::is_ascii_digit) {
+            i += 1;
+        }
+        i
+    };
+    i = skip_number(i);
+    if spec.get(i) == Some(&b'.') {
+        i = skip_number(i + 1);
+    }
+    /* Length modifier */
+    if spec[i..].starts_with(b"hh") || spec[i..].starts_with(b"ll") {
+        i += 2;
+    } else if matches!(
+        spec.get(i),
+        Some(b'h' | b'l' | b'L' | b'q' | b'j' | b'z' | b'Z' | b't')
+    ) {
+        i += 1;
+    }
+
+    match spec.get(i)? {
+        b'd' | b'i' | b'o' | b'u' | b'x' | b'X' | b'c' | b's' | b'f' | b'F' | b'e' | b'E'
+        | b'g' | b'G' | b'a' | b'A' | b'n' => Some(i + 1),
+        b'p' => {
+            i += 1;
+            while spec.get(i).is_some_and(u8::is_ascii_alphanumeric) {
+                i += 1;
+            }
+            Some(i)
+        }
+        _ => None,
+    }
+}
+
+/// Find the parameters (`@dev:`) and sections (`Return:`) of a kernel-doc comment
+///
+/// Parameters are also found where the description refers to them (`@dev`).
+fn kernel_doc(text: &str) -> Vec<(usize, usize, &'static str)> {
+    let mut spans = Vec::new();
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        let body = line.trim_start_matches([' ', '\t', '/', '*']);
+        let body_start = offset + line.len() - body.len();
+        offset += line.len();
+
+        if let Some(len) = section_len(body) {
+            spans.push((body_start, body_start + len, "label"));
+            continue;
+        }
+
+        let mut i = 0;
+        while let Some(pos) = body[i..].find('@') {
+            let start = i + pos;
+            let name_len = body[start + 1..]
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(body.len() - start - 1);
+            i = start + 1 + name_len;
+            if name_len == 0 {
+                continue;
+            }
+            /* The colon belongs to the parameter where it is described */
+            if start == 0 && body[i..].starts_with(':') {
+                i += 1;
+            }
+            spans.push((body_start + start, body_start + i, "variable.parameter"));
+        }
+    }
+    spans
+}
+
+/// Get the length of the section label (`Return:`, `Return value:`) a line starts with
+fn section_len(body: &str) -> Option<usize> {
+    let colon = body.find(':')?;
+    let label = &body[..colon];
+    let mut words = label.split(' ');
+    let first = words.next()?;
+    let is_label = first.starts_with(|c: char| c.is_ascii_uppercase())
+        && first[1..].chars().all(|c| c.is_ascii_lowercase())
+        && first.len() > 1
+        && words.clone().count() <= 1
+        && words.all(|w| !w.is_empty() && w.chars().all(|c| c.is_ascii_lowercase()));
+    let ends = body[colon + 1..].is_empty() || body[colon + 1..].starts_with(char::is_whitespace);
+    (is_label && ends).then_some(colon + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Get the highlighted text of every span with its capture name
+    fn captures<'a>(
+        text: &'a str,
+        spans: &[(usize, usize, &'static str)],
+    ) -> Vec<(&'a str, &'static str)> {
+        spans
+            .iter()
+            .map(|&(start, end, capture)| (&text[start..end], capture))
+            .collect()
+    }
+
+    #[test]
+    fn asm_over_several_strings() {
+        let text = "\"1: movl %%eax, %0\\n\\t\"\n\
+                    \t\"addl $1, %w[val] # bump\\n\"";
+        assert_eq!(
+            captures(text, &gnu_asm(text)),
+            [
+                ("1:", "label"),
+                ("movl", "keyword"),
+                ("%%eax", "variable.builtin"),
+                ("%0", "variable.parameter"),
+                ("addl", "keyword"),
+                ("$1", "number"),
+                ("%w[val]", "variable.parameter"),
+                ("# bump", "comment"),
+            ]
+        );
+    }
+
+    #[test]
+    fn asm_outside_the_strings_is_left_alone() {
+        let text = "\"mov \" __stringify(X) \"\\n\"";
+        assert_eq!(captures(text, &gnu_asm(text)), [("mov", "keyword")]);
+    }
+
+    #[test]
+    fn format_specifiers_with_length_modifiers() {
+        let text = "%d%% of %llu, %-08lx %hhx %zu %.*s";
+        let specifiers: Vec<_> = captures(text, &format_specifiers(text))
+            .into_iter()
+            .map(|(specifier, _)| specifier)
+            .collect();
+        assert_eq!(
+            specifiers,
+            ["%d", "%%", "%llu", "%-08lx", "%hhx", "%zu", "%.*s"]
+        );
+    }
+
+    #[test]
+    fn kernel_pointer_specifiers() {
+        let text = "node %pOF: %*ph, 100%";
+        let specifiers: Vec<_> = captures(text, &format_specifiers(text))
+            .into_iter()
+            .map(|(specifier, _)| specifier)
+            .collect();
+        assert_eq!(specifiers, ["%pOF", "%*ph"]);
+    }
+
+    #[test]
+    fn kernel_doc_parameters_and_sections() {
+        let text = "/**\n\
+                    \x20* foo() - do a thing\n\
+                    \x20* @dev: the device\n\
+                    \x20* @flags: how, see @dev\n\
+                    \x20*\n\
+                    \x20* Return: 0 on success\n\
+                    \x20*/";
+        assert_eq!(
+            captures(text, &kernel_doc(text)),
+            [
+                ("@dev:", "variable.parameter"),
+                ("@flags:", "variable.parameter"),
+                ("@dev", "variable.parameter"),
+                ("Return:", "label"),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_fallback_for_other_languages() {
+        assert!(fallback("c", "int a;").is_none());
+        assert!(fallback("printf", "%d").is_some());
+    }
+}
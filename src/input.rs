@@ -0,0 +1,31 @@
+use std::fs;
+use std::io::{self, Read};
+
+use flate2::read::MultiGzDecoder;
+
+/// Magic bytes at the start of every gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Read the whole input from `path`, or from stdin if it is `None`
+///
+/// Gzip compressed input is decompressed transparently. The bytes are
+/// returned as they are, as emails in legacy encodings are not unusual:
+/// every message is converted to UTF-8 according to its own charset (see
+/// `mime::decode_parts`).
+pub fn read_input(path: Option<&str>) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    match path {
+        Some(path) => bytes = fs::read(path)?,
+        None => {
+            io::stdin().read_to_end(&mut bytes)?;
+        }
+    }
+
+    if bytes.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = Vec::new();
+        MultiGzDecoder::new(bytes.as_slice()).read_to_end(&mut decompressed)?;
+        bytes = decompressed;
+    }
+
+    Ok(bytes)
+}
@@ -0,0 +1,276 @@
+use std::sync::OnceLock;
+
+use tree_sitter_language::LanguageFn;
+
+/// The tree-sitter grammar of a language, with its queries
+#[derive(Clone)]
+pub struct Grammar {
+    pub language: LanguageFn,
+    pub highlights: &'static str,
+    /// Highlight patterns layered on top of `highlights`, they take precedence over them
+    pub highlight_layer: &'static str,
+    pub injections: &'static str,
+    /// Injection patterns added to `injections`
+    pub injection_layer: &'static str,
+    pub locals: &'static str,
+}
+
+/// A language of the files a patch can touch
+///
+/// The `id` is stable: it is what the parser puts on `HunkHeader` and `Code`
+/// lines, and what the highlighter configs are keyed by.
+#[derive(Clone)]
+pub struct Language {
+    pub id: &'static str,
+    /// File extensions, without the dot
+    pub extensions: &'static [&'static str],
+    /// File names of extensionless files, which also match with a suffix (`Kconfig.debug`)
+    pub file_names: &'static [&'static str],
+    /// The grammar, `None` if there is no grammar crate for the language and none was loaded
+    pub grammar: Option<Grammar>,
+}
+
+/// Language id of the files that match no language
+pub const UNKNOWN: &str = "unknown";
+
+/// The languages in use, if they differ from the bundled ones (see `register`)
+static REGISTRY: OnceLock<Vec<Language>> = OnceLock::new();
+
+/// Every language bundled with kmailight
+pub const LANGUAGES: &[Language] = &[
+    Language {
+        id: "c",
+        extensions: &["c", "h"],
+        file_names: &[],
+        grammar: Some(Grammar {
+            language: tree_sitter_c::LANGUAGE,
+            highlights: tree_sitter_c::HIGHLIGHT_QUERY,
+            highlight_layer: include_str!("../queries/c/kernel.scm"),
+            injections: include_str!("../queries/c/injections.scm"),
+            injection_layer: "",
+            locals: "",
+        }),
+    },
+    Language {
+        id: "rust",
+        extensions: &["rs"],
+        file_names: &[],
+        grammar: Some(Grammar {
+            language: tree_sitter_rust::LANGUAGE,
+            highlights: tree_sitter_rust::HIGHLIGHTS_QUERY,
+            highlight_layer: "",
+            injections: tree_sitter_rust::INJECTIONS_QUERY,
+            injection_layer: include_str!("../queries/rust/injections.scm"),
+            locals: "",
+        }),
+    },
+    Language {
+        id: "python",
+        extensions: &["py"],
+        file_names: &[],
+        grammar: Some(Grammar {
+            language: tree_sitter_python::LANGUAGE,
+            highlights: tree_sitter_python::HIGHLIGHTS_QUERY,
+            highlight_layer: "",
+            injections: "",
+            injection_layer: "",
+            locals: "",
+        }),
+    },
+    Language {
+        id: "bash",
+        extensions: &["sh", "bash"],
+        file_names: &[],
+        grammar: Some(Grammar {
+            language: tree_sitter_bash::LANGUAGE,
+            highlights: tree_sitter_bash::HIGHLIGHT_QUERY,
+            highlight_layer: "",
+            injections: "",
+            injection_layer: "",
+            locals: "",
+        }),
+    },
+    Language {
+        id: "yaml",
+        extensions: &["yaml", "yml"],
+        file_names: &[],
+        grammar: Some(Grammar {
+            language: tree_sitter_yaml::LANGUAGE,
+            highlights: tree_sitter_yaml::HIGHLIGHTS_QUERY,
+            highlight_layer: "",
+            injections: "",
+            injection_layer: "",
+            locals: "",
+        }),
+    },
+    Language {
+        id: "markdown",
+        extensions: &["md"],
+        file_names: &[],
+        grammar: Some(Grammar {
+            language: tree_sitter_md::LANGUAGE,
+            highlights: tree_sitter_md::HIGHLIGHT_QUERY_BLOCK,
+            highlight_layer: "",
+            injections: include_str!("../queries/markdown/injections.scm"),
+            injection_layer: "",
+            locals: "",
+        }),
+    },
+    /* The inline content of Markdown blocks, only ever injected */
+    Language {
+        id: "markdown_inline",
+        extensions: &[],
+        file_names: &[],
+        grammar: Some(Grammar {
+            language: tree_sitter_md::INLINE_LANGUAGE,
+            highlights: tree_sitter_md::HIGHLIGHT_QUERY_INLINE,
+            highlight_layer: "",
+            injections: tree_sitter_md::INJECTION_QUERY_INLINE,
+            injection_layer: "",
+            locals: "",
+        }),
+    },
+    Language {
+        id: "asm",
+        extensions: &["S", "s"],
+        file_names: &[],
+        grammar: None,
+    },
+    Language {
+        id: "make",
+        extensions: &["mk", "mak"],
+        file_names: &["Makefile", "makefile", "GNUmakefile", "Kbuild"],
+        grammar: Some(Grammar {
+            language: tree_sitter_make::LANGUAGE,
+            highlights: tree_sitter_make::HIGHLIGHTS_QUERY,
+            highlight_layer: include_str!("../queries/make/highlights.scm"),
+            injections: "",
+            injection_layer: "",
+            locals: "",
+        }),
+    },
+    Language {
+        id: "kconfig",
+        extensions: &[],
+        file_names: &["Kconfig"],
+        grammar: Some(Grammar {
+            language: tree_sitter_kconfig::LANGUAGE,
+            highlights: tree_sitter_kconfig::HIGHLIGHTS_QUERY,
+            highlight_layer: include_str!("../queries/kconfig/highlights.scm"),
+            injections: tree_sitter_kconfig::INJECTIONS_QUERY,
+            injection_layer: "",
+            locals: tree_sitter_kconfig::LOCALS_QUERY,
+        }),
+    },
+    Language {
+        id: "devicetree",
+        extensions: &["dts", "dtsi", "dtso"],
+        file_names: &[],
+        grammar: Some(Grammar {
+            language: tree_sitter_devicetree::LANGUAGE,
+            highlights: tree_sitter_devicetree::HIGHLIGHTS_QUERY,
+            highlight_layer: include_str!("../queries/devicetree/highlights.scm"),
+            injections: "",
+            injection_layer: "",
+            locals: "",
+        }),
+    },
+    Language {
+        id: "rst",
+        extensions: &["rst"],
+        file_names: &[],
+        grammar: Some(Grammar {
+            language: tree_sitter_rst::LANGUAGE,
+            highlights: include_str!("../queries/rst/highlights.scm"),
+            highlight_layer: "",
+            injections: "",
+            injection_layer: "",
+            locals: "",
+        }),
+    },
+];
+
+/// Replace the bundled languages with `languages` (e.g. extended by `loader::load_languages`)
+///
+/// This must happen at startup, before anything is parsed or highlighted.
+/// Only the first call has an effect.
+pub fn register(languages: Vec<Language>) {
+    let _ = REGISTRY.set(languages);
+}
+
+/// Get the languages in use, the bundled ones unless others were registered
+pub fn languages() -> &'static [Language] {
+    REGISTRY.get().map_or(LANGUAGES, Vec::as_slice)
+}
+
+/// Get a language by its id, or by the extension of its files (`sh`, `yml`)
+///
+/// This is how injections name the languages they nest.
+pub fn by_name(name: &str) -> Option<&'static Language> {
+    let languages = languages();
+    languages
+        .iter()
+        .find(|l| l.id == name)
+        .or_else(|| languages.iter().find(|l| l.extensions.contains(&name)))
+}
+
+/// Detect the language of a file from its name, or from its extension
+pub fn detect(file_path: &str) -> Option<&'static Language> {
+    let file_name = file_path.rsplit('/').next().unwrap_or(file_path);
+
+    let by_name = languages().iter().find(|l| {
+        l.file_names.iter().any(|&name| {
+            file_name
+                .strip_prefix(name)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+        })
+    });
+    by_name.or_else(|| {
+        let (_, extension) = file_name.rsplit_once('.')?;
+        languages()
+            .iter()
+            .find(|l| l.extensions.contains(&extension))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detected(file_path: &str) -> Option<&'static str> {
+        detect(file_path).map(|l| l.id)
+    }
+
+    #[test]
+    fn kernel_build_files() {
+        assert_eq!(detected("drivers/net/Kbuild"), Some("make"));
+        assert_eq!(detected("scripts/Makefile.lib"), Some("make"));
+        assert_eq!(detected("arch/x86/Kconfig"), Some("kconfig"));
+        assert_eq!(detected("lib/Kconfig.debug"), Some("kconfig"));
+    }
+
+    #[test]
+    fn file_name_must_match_up_to_a_dot() {
+        assert_eq!(detected("tools/Kconfigure.c"), Some("c"));
+        assert_eq!(detected("Kbuilder"), None);
+    }
+
+    #[test]
+    fn device_tree_sources() {
+        assert_eq!(
+            detected("arch/arm64/boot/dts/vendor/board.dts"),
+            Some("devicetree")
+        );
+        assert_eq!(
+            detected("arch/arm64/boot/dts/vendor/soc.dtsi"),
+            Some("devicetree")
+        );
+    }
+
+    #[test]
+    fn by_extension() {
+        assert_eq!(detected("mm/slab.c"), Some("c"));
+        assert_eq!(detected("Documentation/process/howto.rst"), Some("rst"));
+        assert_eq!(detected("README"), None);
+    }
+}
@@ -0,0 +1,234 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use libloading::Library;
+use tree_sitter_language::LanguageFn;
+
+use crate::language::{Grammar, LANGUAGES, Language};
+
+/// Get the directory of the user configuration
+///
+/// This is `$XDG_CONFIG_HOME/kmailight`, or `~/.config/kmailight` if the
+/// variable is not set.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("kmailight"))
+}
+
+/// Load the languages of the `languages` directory of `config_dir` on top of the bundled ones
+///
+/// Every subdirectory is a language, named after its id, which may contain:
+///
+/// - `parser.so` (or `<id>.so`), a grammar built by `tree-sitter build`
+/// - `highlights.scm`, `injections.scm`, and `locals.scm` queries
+/// - `filetypes`, the extensions (`.S`) and file names (`Kbuild`) of the
+///   language, one per line
+///
+/// A bundled language keeps whatever is not overridden, including its
+/// highlight layer (e.g. the kernel idioms on top of C). A new language
+/// needs a grammar and a highlights query. Languages that fail to load are
+/// reported and skipped.
+pub fn load_languages(config_dir: &Path) -> Vec<Language> {
+    let mut languages = LANGUAGES.to_vec();
+
+    let Ok(entries) = fs::read_dir(config_dir.join("languages")) else {
+        return languages;
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+
+    for dir in dirs {
+        let Some(id) = dir.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let bundled = languages.iter().position(|l| l.id == id);
+        let base = bundled.map(|i| languages[i].clone());
+
+        match load_language(&dir, leak(id.to_string()), base) {
+            Ok(language) => match bundled {
+                Some(i) => languages[i] = language,
+                None => languages.push(language),
+            },
+            Err(err) => eprintln!("kmailight: {}: {err}", dir.display()),
+        }
+    }
+    languages
+}
+
+/// Load the language in `dir`, on top of the bundled language `base` if any
+fn load_language(dir: &Path, id: &'static str, base: Option<Language>) -> io::Result<Language> {
+    let mut language = base.unwrap_or(Language {
+        id,
+        extensions: &[],
+        file_names: &[],
+        grammar: None,
+    });
+
+    if let Some(language_fn) = load_grammar(dir, id)? {
+        match language.grammar.as_mut() {
+            Some(grammar) => grammar.language = language_fn,
+            None => {
+                language.grammar = Some(Grammar {
+                    language: language_fn,
+                    highlights: "",
+                    highlight_layer: "",
+                    injections: "",
+                    injection_layer: "",
+                    locals: "",
+                })
+            }
+        }
+    }
+    let Some(grammar) = language.grammar.as_mut() else {
+        return Err(io::Error::other(format!("no grammar for language '{id}'")));
+    };
+
+    if let Some(highlights) = read_file(dir, "highlights.scm")? {
+        grammar.highlights = highlights;
+    }
+    if let Some(injections) = read_file(dir, "injections.scm")? {
+        grammar.injections = injections;
+    }
+    if let Some(locals) = read_file(dir, "locals.scm")? {
+        grammar.locals = locals;
+    }
+    if grammar.highlights.is_empty() {
+        return Err(io::Error::other(format!(
+            "no highlights.scm for language '{id}'"
+        )));
+    }
+
+    if let Some(filetypes) = read_file(dir, "filetypes")? {
+        let (extensions, file_names): (Vec<_>, Vec<_>) = filetypes
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .partition(|line| line.starts_with('.'));
+        let extensions = extensions.iter().map(|ext| &ext[1..]);
+
+        language.extensions = leak_slice(language.extensions.iter().copied().chain(extensions));
+        language.file_names = leak_slice(language.file_names.iter().copied().chain(file_names));
+    }
+    Ok(language)
+}
+
+/// Load the grammar library of a language, if there is one
+///
+/// The library stays loaded for the whole run, as the highlighter holds on
+/// to the grammar.
+fn load_grammar(dir: &Path, id: &str) -> io::Result<Option<LanguageFn>> {
+    let Some(path) = [
+        format!("parser.{}", env::consts::DLL_EXTENSION),
+        format!("{id}.{}", env::consts::DLL_EXTENSION),
+    ]
+    .iter()
+    .map(|name| dir.join(name))
+    .find(|path| path.is_file()) else {
+        return Ok(None);
+    };
+
+    /* tree-sitter names the entry point of a grammar after it, with `_` for `-` */
+    let symbol = format!("tree_sitter_{}", id.replace('-', "_"));
+
+    // SAFETY: the library is a tree-sitter grammar the user installed on purpose,
+    // and `tree_sitter_<id>` is its entry point, which takes no arguments and
+    // returns a pointer to the static language definition.
+    unsafe {
+        let library = Library::new(&path).map_err(io::Error::other)?;
+        let entry = *library
+            .get::<unsafe extern "C" fn() -> *const ()>(symbol.as_bytes())
+            .map_err(io::Error::other)?;
+        std::mem::forget(library);
+        Ok(Some(LanguageFn::from_raw(entry)))
+    }
+}
+
+/// Read a file of a language (a query, the file types), if there is one
+fn read_file(dir: &Path, name: &str) -> io::Result<Option<&'static str>> {
+    match fs::read_to_string(dir.join(name)) {
+        Ok(text) => Ok(Some(leak(text))),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/* The languages are loaded once and used until the end of the run, so leaking is fine */
+
+fn leak(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
+fn leak_slice(items: impl Iterator<Item = &'static str>) -> &'static [&'static str] {
+    Box::leak(items.collect::<Vec<_>>().into_boxed_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create an empty directory for a test under the temporary directory
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("kmailight-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn config_dir_follows_xdg_config_home() {
+        // SAFETY: no other test reads or writes these variables
+        unsafe {
+            env::set_var("XDG_CONFIG_HOME", "/tmp/xdg");
+            env::set_var("HOME", "/home/user");
+        }
+        assert_eq!(config_dir(), Some(PathBuf::from("/tmp/xdg/kmailight")));
+
+        // SAFETY: see above
+        unsafe { env::set_var("XDG_CONFIG_HOME", "") };
+        assert_eq!(
+            config_dir(),
+            Some(PathBuf::from("/home/user/.config/kmailight"))
+        );
+    }
+
+    #[test]
+    fn bundled_language_is_extended() {
+        let config_dir = test_dir("extended");
+        let c = config_dir.join("languages/c");
+        fs::create_dir_all(&c).unwrap();
+        fs::write(c.join("highlights.scm"), "(identifier) @variable\n").unwrap();
+        fs::write(c.join("filetypes"), "# Coccinelle\n.cocci\n\nKbuild.c\n").unwrap();
+
+        let languages = load_languages(&config_dir);
+        let c = languages.iter().find(|l| l.id == "c").unwrap();
+        assert_eq!(c.extensions, ["c", "h", "cocci"]);
+        assert_eq!(c.file_names, ["Kbuild.c"]);
+        let grammar = c.grammar.as_ref().unwrap();
+        assert_eq!(grammar.highlights, "(identifier) @variable\n");
+
+        fs::remove_dir_all(&config_dir).unwrap();
+    }
+
+    #[test]
+    fn new_language_without_a_grammar_is_skipped() {
+        let config_dir = test_dir("skipped");
+        let dir = config_dir.join("languages/cocci");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("highlights.scm"), "(identifier) @variable\n").unwrap();
+
+        let languages = load_languages(&config_dir);
+        assert_eq!(languages.len(), LANGUAGES.len());
+        assert!(languages.iter().all(|l| l.id != "cocci"));
+
+        fs::remove_dir_all(&config_dir).unwrap();
+    }
+}
@@ -1,31 +1,43 @@
-use std::env;
-use std::fs;
-use std::io::{self, Read};
-
-use crate::parser::line::Line;
+use std::io::{self, Write};
 
+mod cli;
 mod debug;
 mod highlighter;
+mod injection;
+mod input;
+mod language;
+mod loader;
+mod mbox;
 
 mod parser;
+mod renderer;
+mod theme;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let source_code = if let Some(path) = env::args().nth(1) {
-        fs::read_to_string(path)?
+    let options = cli::Options::parse();
+    let input = input::read_input(options.path.as_deref())?;
+
+    let config_dir = loader::config_dir();
+    if let Some(config_dir) = &config_dir {
+        language::register(loader::load_languages(config_dir));
+    }
+    let theme =
+        theme::load(config_dir.as_deref(), options.theme.as_deref()).unwrap_or_else(|err| {
+            eprintln!("kmailight: {err}");
+            theme::Theme::default()
+        });
+    let mut highlighter = highlighter::HighlighterEngine::new(theme);
+
+    let output = if options.mbox {
+        let messages = mbox::split_messages(&input);
+        renderer::render_mbox(&mut highlighter, &messages, options.quote)
     } else {
-        let mut buf = String::new();
-        io::stdin().read_to_string(&mut buf)?;
-        buf
+        renderer::render_message(&mut highlighter, &input, options.quote)
     };
 
-    let highlighter = highlighter::HighlighterEngine::new();
-
-    let lines = Line::parse_lines(&source_code);
-    debug::print_lines(&lines);
-
-    let spans = parser::span::build_spans(&lines);
-    debug::print_spans(&spans);
-
-    println!();
-    Ok(())
+    /* The pager may quit before reading everything, which is not an error */
+    match io::stdout().lock().write_all(output.as_bytes()) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
 }
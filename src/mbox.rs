@@ -0,0 +1,91 @@
+/// Split an mbox into its messages
+///
+/// A message starts with a `From ` line at the beginning of the mbox or
+/// right after an empty line. The `From ` line is kept, as it is part of
+/// the header block (see `Headers::parse`). Both mboxo and mboxrd escaping
+/// is undone: one `>` is removed from every `>From `, `>>From `, etc. line.
+/// The messages are kept as bytes, since each one has its own charset.
+pub fn split_messages(mbox: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut current = Vec::new();
+    let mut after_empty = true;
+
+    for chunk in mbox.split_inclusive(|&b| b == b'\n') {
+        let line = chunk.strip_suffix(b"\n").unwrap_or(chunk);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        if after_empty && line.starts_with(b"From ") && !current.is_empty() {
+            messages.push(std::mem::take(&mut current));
+        }
+
+        let unquoted = line.iter().position(|&b| b != b'>').unwrap_or(line.len());
+        if unquoted > 0 && line[unquoted..].starts_with(b"From ") {
+            current.extend_from_slice(&chunk[1..]);
+        } else {
+            current.extend_from_slice(chunk);
+        }
+
+        after_empty = line.is_empty();
+    }
+
+    if !current.is_empty() {
+        messages.push(current);
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_at_from_lines_after_empty_lines() {
+        let mbox = b"From a@b Mon Jan  1 00:00:00 2024\n\
+                     Subject: one\n\
+                     \n\
+                     body\n\
+                     \n\
+                     From c@d Mon Jan  1 00:00:00 2024\n\
+                     Subject: two\n";
+        let messages = split_messages(mbox);
+
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].starts_with(b"From a@b"));
+        assert!(messages[0].ends_with(b"body\n\n"));
+        assert!(messages[1].starts_with(b"From c@d"));
+    }
+
+    #[test]
+    fn from_in_the_middle_of_a_paragraph_does_not_split() {
+        let mbox = b"From a\n\nsome text\nFrom here on\n";
+        assert_eq!(split_messages(mbox).len(), 1);
+    }
+
+    #[test]
+    fn escaped_from_lines_are_unescaped() {
+        let mbox = b"From a\n\n>From mboxo\n>>From mboxrd\n> From quoted\n>Fromage\n";
+        let messages = split_messages(mbox);
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0],
+            b"From a\n\nFrom mboxo\n>From mboxrd\n> From quoted\n>Fromage\n"
+        );
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let mbox = b"From a\r\nSubject: one\r\n\r\n>From x\r\n\r\nFrom b\r\n";
+        let messages = split_messages(mbox);
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0], b"From a\r\nSubject: one\r\n\r\nFrom x\r\n\r\n");
+        assert_eq!(messages[1], b"From b\r\n");
+    }
+
+    #[test]
+    fn legacy_charsets_are_kept_as_bytes() {
+        let messages = split_messages(b"From a\n\ncaf\xe9\n");
+        assert_eq!(messages[0], b"From a\n\ncaf\xe9\n");
+    }
+}
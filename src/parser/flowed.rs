@@ -0,0 +1,177 @@
+/// Prefixes of the lines that start a patch
+const PATCH_STARTS: &[&str] = &["diff ", "Index: ", "--- ", "@@ "];
+
+/// Prefixes of the extended git diff metadata lines
+const PATCH_METADATA: &[&str] = &[
+    "index ",
+    "new file mode ",
+    "deleted file mode ",
+    "old mode ",
+    "new mode ",
+    "similarity index ",
+    "dissimilarity index ",
+    "rename ",
+    "copy ",
+    "Binary files ",
+    "GIT binary patch",
+];
+
+/// Decode a `format=flowed` text (RFC 3676)
+///
+/// A line that ends in a space is a soft break and is joined with the next
+/// line of the same quote depth, without that space if `delsp` is set. The
+/// quote marks and the space-stuffing of the joined lines are dropped, while
+/// the first line of each paragraph keeps its prefix as written. Patches are
+/// left untouched, since trailing whitespace is part of their content. A
+/// patch is tracked per quote depth, so a quoted patch that a reply at
+/// another depth interrupts is still a patch where it resumes.
+pub fn unflow(text: &str, delsp: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    /* Whether each quote depth is in a patch */
+    let mut patches: Vec<bool> = Vec::new();
+    /* Quote depth and line terminator of a pending soft break */
+    let mut soft_break: Option<(usize, &str)> = None;
+
+    for chunk in text.split_inclusive('\n') {
+        let line = chunk.trim_end_matches(['\n', '\r']);
+        let terminator = &chunk[line.len()..];
+        let depth = line.len() - line.trim_start_matches('>').len();
+        let content = &line[depth..];
+        let unstuffed = content.strip_prefix(' ').unwrap_or(content);
+        /* The signature separator ends in a space, but never flows */
+        let signature = unstuffed == "-- ";
+
+        if patches.len() <= depth {
+            patches.resize(depth + 1, false);
+        }
+        /* The space after `>` is stuffing, an unquoted one can be a context marker */
+        let marker = if depth > 0 { unstuffed } else { content };
+        patches[depth] = if patches[depth] {
+            continues_patch(marker)
+        } else {
+            starts_patch(unstuffed)
+        };
+        let in_patch = patches[depth];
+
+        match soft_break.take() {
+            Some((open_depth, _)) if open_depth == depth && !in_patch && !signature => {
+                if delsp {
+                    out.pop();
+                }
+                out.push_str(unstuffed);
+            }
+            pending => {
+                /* A change of quote depth, a patch, or a signature ends the paragraph */
+                if let Some((_, pending_terminator)) = pending {
+                    out.push_str(pending_terminator);
+                }
+                out.push_str(line);
+            }
+        }
+
+        if !in_patch && !signature && line.ends_with(' ') {
+            soft_break = Some((depth, terminator));
+        } else {
+            out.push_str(terminator);
+        }
+    }
+
+    if let Some((_, terminator)) = soft_break {
+        out.push_str(terminator);
+    }
+    out
+}
+
+/// Check if an unquoted line starts a patch
+#[inline]
+fn starts_patch(line: &str) -> bool {
+    PATCH_STARTS.iter().any(|p| line.starts_with(p))
+}
+
+/// Check if an unquoted line can be part of the patch the previous line belongs to
+#[inline]
+fn continues_patch(line: &str) -> bool {
+    line.is_empty()
+        || line.starts_with([' ', '+', '-', '@', '\\'])
+        || starts_patch(line)
+        || PATCH_METADATA.iter().any(|p| line.starts_with(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soft_breaks_are_joined() {
+        assert_eq!(
+            unflow("one \ntwo \nthree\nfour\n", false),
+            "one two three\nfour\n"
+        );
+        assert_eq!(unflow("one \r\ntwo\r\n", false), "one two\r\n");
+        assert_eq!(unflow("trailing \n", false), "trailing \n");
+    }
+
+    #[test]
+    fn delsp_drops_the_soft_break_space() {
+        assert_eq!(unflow("exam \nple\n", true), "example\n");
+    }
+
+    #[test]
+    fn quoted_lines_join_at_the_same_depth() {
+        assert_eq!(unflow("> one \n> two\n", false), "> one two\n");
+        assert_eq!(unflow(">> one \n> two\n", false), ">> one \n> two\n");
+        assert_eq!(unflow("> one \ntwo\n", false), "> one \ntwo\n");
+    }
+
+    #[test]
+    fn space_stuffing_of_joined_lines_is_dropped() {
+        assert_eq!(
+            unflow(" From me \n From you\n", false),
+            " From me From you\n"
+        );
+    }
+
+    #[test]
+    fn patches_are_left_untouched() {
+        let patch = "diff --git a/f b/f\n\
+                     @@ -1,2 +1,2 @@\n\
+                     \x20context \n\
+                     -old \n\
+                     +new \n";
+        assert_eq!(unflow(patch, true), patch);
+    }
+
+    #[test]
+    fn quoted_patch_interrupted_by_a_reply() {
+        let text = "> diff --git a/f.c b/f.c\n\
+                    > @@ -1,3 +1,3 @@\n\
+                    >  int a; \n\
+                    \n\
+                    Why is this needed?\n\
+                    \n\
+                    >  \n\
+                    > -int b;\n\
+                    > +int c;\n\
+                    > Some quoted \n\
+                    > prose.\n";
+        assert_eq!(
+            unflow(text, false),
+            "> diff --git a/f.c b/f.c\n\
+             > @@ -1,3 +1,3 @@\n\
+             >  int a; \n\
+             \n\
+             Why is this needed?\n\
+             \n\
+             >  \n\
+             > -int b;\n\
+             > +int c;\n\
+             > Some quoted prose.\n"
+        );
+    }
+
+    #[test]
+    fn signature_separator_does_not_flow() {
+        let text = "thanks \n-- \nJane \nDoe\n";
+        assert_eq!(unflow(text, false), "thanks \n-- \nJane Doe\n");
+    }
+}
@@ -0,0 +1,225 @@
+/// Header names that mark the beginning of an email header block
+///
+/// A block of `Name: value` lines is only treated as email headers if
+/// it contains at least one of these, to avoid mistaking a message that
+/// starts with something like `Note: ...` for headers.
+const KNOWN_HEADERS: &[&str] = &[
+    "From",
+    "To",
+    "Cc",
+    "Subject",
+    "Date",
+    "Message-ID",
+    "In-Reply-To",
+    "References",
+    "Reply-To",
+    "MIME-Version",
+    "Content-Type",
+    "Content-Transfer-Encoding",
+    "Content-Disposition",
+];
+
+/// A single header field with its folded continuation lines joined
+#[derive(Debug, Clone)]
+pub struct HeaderField<'a> {
+    /// Name of the field as written in the source (e.g. `Message-Id`)
+    pub name: &'a str,
+    /// Unfolded value without the leading and trailing whitespace
+    pub value: String,
+    /// Offset of the first line of the field
+    #[cfg(debug_assertions)]
+    pub offset: usize,
+    /// Length of the field including all continuation lines
+    pub length: usize,
+}
+
+/// The RFC 5322 header block at the top of an email
+///
+/// Produced by `git format-patch`, `b4 am`, mbox files, etc.
+/// The block ends with the first empty line. An mbox `From ` envelope
+/// line in front of the block is part of it, but it is not a field.
+#[derive(Debug, Clone, Default)]
+pub struct Headers<'a> {
+    pub fields: Vec<HeaderField<'a>>,
+    /// Offset right after the last header line (the start of the empty line)
+    pub end: usize,
+}
+
+impl<'a> Headers<'a> {
+    /// Parse the header block at the beginning of `source`
+    ///
+    /// Returns an empty `Headers` (with `end` being 0) if the source does
+    /// not start with a header block.
+    pub fn parse(source: &'a str) -> Headers<'a> {
+        let mut fields: Vec<HeaderField> = Vec::new();
+        let mut offset = 0usize;
+
+        for chunk in source.split_inclusive('\n') {
+            let raw = chunk.trim_end_matches(['\n', '\r']);
+
+            if raw.is_empty() {
+                break;
+            }
+
+            if offset == 0 && raw.starts_with("From ") {
+                /* mbox envelope line */
+            } else if raw.starts_with([' ', '\t']) {
+                let Some(field) = fields.last_mut() else {
+                    return Headers::default();
+                };
+                field.value.push_str(raw);
+                field.length += chunk.len();
+            } else if let Some((name, value)) = split_field(raw) {
+                fields.push(HeaderField {
+                    name,
+                    value: value.to_string(),
+                    #[cfg(debug_assertions)]
+                    offset,
+                    length: chunk.len(),
+                });
+            } else {
+                return Headers::default();
+            }
+
+            offset += chunk.len();
+        }
+
+        let known = fields
+            .iter()
+            .any(|f| KNOWN_HEADERS.iter().any(|k| k.eq_ignore_ascii_case(f.name)));
+        if !known {
+            return Headers::default();
+        }
+
+        for field in &mut fields {
+            field.value = field.value.trim().to_string();
+        }
+        Headers {
+            fields,
+            end: offset,
+        }
+    }
+
+    /// Get the value of the first field with the given name (case-insensitive)
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|f| f.name.eq_ignore_ascii_case(name))
+            .map(|f| f.value.as_str())
+    }
+
+    /// Get the `Subject:` of the email
+    pub fn subject(&self) -> Option<&str> {
+        self.get("Subject")
+    }
+
+    /// Get the `Message-ID:` of the email without the angle brackets
+    #[cfg(debug_assertions)]
+    pub fn message_id(&self) -> Option<&str> {
+        self.get("Message-ID").map(strip_angle_brackets)
+    }
+
+    /// Get the `In-Reply-To:` of the email without the angle brackets
+    #[cfg(debug_assertions)]
+    pub fn in_reply_to(&self) -> Option<&str> {
+        self.get("In-Reply-To").map(strip_angle_brackets)
+    }
+}
+
+/// Split a header line into its field name and value
+///
+/// Field names consist of printable ASCII characters except `:` (RFC 5322).
+pub fn split_field(line: &str) -> Option<(&str, &str)> {
+    let (name, value) = line.split_once(':')?;
+    if name.is_empty() || !name.bytes().all(|b| (33..=126).contains(&b)) {
+        return None;
+    }
+    Some((name, value))
+}
+
+/// Get the length of the subject tags (`[PATCH v3 2/7]`, `Re:`, etc.)
+///
+/// These are the bracketed groups and reply/forward markers at the
+/// start of a subject, including the whitespace between them.
+pub fn subject_tags_len(subject: &str) -> usize {
+    let mut end = 0;
+    loop {
+        let rest = &subject[end..];
+        let skipped = rest.len() - rest.trim_start().len();
+        let rest = rest.trim_start();
+
+        let tag_len = if rest.starts_with('[') {
+            rest.find(']').map(|i| i + 1)
+        } else {
+            ["Re:", "RE:", "Fwd:", "Fw:"]
+                .iter()
+                .find(|p| rest.starts_with(*p))
+                .map(|p| p.len())
+        };
+
+        match tag_len {
+            Some(len) => end += skipped + len,
+            None => return end,
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+#[inline]
+fn strip_angle_brackets(value: &str) -> &str {
+    value.trim().trim_start_matches('<').trim_end_matches('>')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folded_fields_are_unfolded() {
+        let headers = Headers::parse(
+            "From: A U Thor <author@example.org>\n\
+             Subject: [PATCH] a subject\n\
+             \tthat is folded\n\
+             \n\
+             Body\n",
+        );
+        assert_eq!(headers.fields.len(), 2);
+        assert_eq!(headers.subject(), Some("[PATCH] a subject\tthat is folded"));
+        assert_eq!(headers.fields[1].length, 43);
+    }
+
+    #[test]
+    fn header_block_ends_at_the_empty_line() {
+        let source = "From 1234 Mon Sep 17 00:00:00 2001\n\
+                      From: A U Thor <author@example.org>\n\
+                      Subject: a subject\n\
+                      \n\
+                      Note: not a header\n";
+        let headers = Headers::parse(source);
+        assert_eq!(headers.fields.len(), 2);
+        assert_eq!(headers.end, source.find("\n\n").unwrap() + 1);
+        assert_eq!(headers.get("note"), None);
+    }
+
+    #[test]
+    fn unknown_fields_are_not_headers() {
+        let headers = Headers::parse("Note: something\nAnother: thing\n\nBody\n");
+        assert!(headers.fields.is_empty());
+        assert_eq!(headers.end, 0);
+    }
+
+    #[test]
+    fn continuation_without_a_field_is_not_headers() {
+        let headers = Headers::parse(" Subject: indented\n\nBody\n");
+        assert_eq!(headers.end, 0);
+    }
+
+    #[test]
+    fn subject_tags() {
+        assert_eq!(subject_tags_len("[PATCH v2 3/7] mm: fix a leak"), 14);
+        assert_eq!(subject_tags_len("Re: [PATCH v2 3/7] mm: fix a leak"), 18);
+        assert_eq!(subject_tags_len(" [RFC] [PATCH] foo"), 14);
+        assert_eq!(subject_tags_len("mm: fix a leak"), 0);
+        assert_eq!(subject_tags_len("[PATCH unterminated"), 0);
+    }
+}
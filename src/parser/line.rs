@@ -1,24 +1,175 @@
+use crate::language;
+use crate::parser::header::Headers;
+use crate::parser::path::{self, DEV_NULL, PathSide};
+use crate::parser::quote::QuoteProfile;
+
+/// Lines at least this wide may have been wrapped by a mail client
+///
+/// Clients wrap at a column around 72-78, at the last space before it.
+const MIN_WRAP_WIDTH: usize = 60;
+
 #[derive(Debug, Clone)]
 pub enum CodeKind {
     Add,
     Remove,
     Context,
+    /// A line of a combined diff (`diff --cc`), with one marker per parent
+    ///
+    /// Each marker is `Add`, `Remove`, or `Context` for its own column.
+    Combined(Vec<CodeKind>),
+    /// The `\ No newline at end of file` marker, with the kind of the line it applies to
+    ///
+    /// The marker is not part of either side of the diff.
+    NoNewline(Box<CodeKind>),
+}
+
+impl CodeKind {
+    /// Check if the line is part of the file before the change
+    ///
+    /// For combined diffs, this is the case if it is part of any parent.
+    pub fn in_old(&self) -> bool {
+        match self {
+            CodeKind::Add | CodeKind::NoNewline(_) => false,
+            CodeKind::Remove | CodeKind::Context => true,
+            CodeKind::Combined(markers) => (0..markers.len()).any(|i| self.in_parent(i)),
+        }
+    }
+
+    /// Check if the line is part of the file after the change (the merge result)
+    pub fn in_new(&self) -> bool {
+        match self {
+            CodeKind::Add | CodeKind::Context => true,
+            CodeKind::Remove | CodeKind::NoNewline(_) => false,
+            CodeKind::Combined(markers) => !markers.iter().any(|m| matches!(m, CodeKind::Remove)),
+        }
+    }
+
+    /// Check if the line is part of the `parent`-th parent of the change
+    ///
+    /// A `-` column means the line only exists in that parent, a `+` column
+    /// means the parent lacks a line of the result. A blank column of a
+    /// removed line means the line does not exist in that parent either.
+    fn in_parent(&self, parent: usize) -> bool {
+        match self {
+            CodeKind::Combined(markers) => match markers.get(parent) {
+                Some(CodeKind::Remove) => true,
+                Some(CodeKind::Context) => self.in_new(),
+                _ => false,
+            },
+            _ => parent == 0 && self.in_old(),
+        }
+    }
+}
+
+/// The kind of an extended header line between `diff --git` and the first hunk
+///
+/// Modes are kept as written (e.g. `100644`), hashes are the abbreviated
+/// object names of the `index` line. The paths of renames and copies are
+/// recorded on the `DiffHeader` line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataKind {
+    /// `index abc123..def456 100644`
+    Index {
+        old_hash: String,
+        new_hash: String,
+        mode: Option<String>,
+    },
+    /// `new file mode 100644`
+    NewFileMode(String),
+    /// `deleted file mode 100644`
+    DeletedFileMode(String),
+    /// `old mode 100644`
+    OldMode(String),
+    /// `new mode 100755`
+    NewMode(String),
+    /// `similarity index 90%`
+    Similarity(u8),
+    /// `dissimilarity index 60%`
+    Dissimilarity(u8),
+    RenameFrom,
+    RenameTo,
+    CopyFrom,
+    CopyTo,
+    /// `--- a/path`
+    OldFile,
+    /// `+++ b/path`
+    NewFile,
+    /// `Binary files a/x and b/x differ`
+    Binary,
+    /// `GIT binary patch`
+    GitBinaryPatch,
+    /// Anything else (e.g. the `====` line of `svn diff`)
+    Unknown,
+}
+
+/// Damage a mail client did to a patch, found by checking it against its hunk counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Damage {
+    /// The ` ` marker of a context line is gone, usually stripped as trailing whitespace
+    MissingMarker,
+    /// The ` ` marker of a context line was turned into a non-breaking space
+    NonBreakingSpace,
+    /// The continuation of a line that was wrapped, it does not count against the hunk
+    Wrapped,
+    /// The hunk ended before all the lines announced by its header were seen
+    Truncated,
+}
+
+/// The kind of a line of a `GIT binary patch` block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryKind {
+    /// `literal N`, the full content of the file, `N` bytes inflated
+    Literal(usize),
+    /// `delta N`, a delta against the other side, `N` bytes inflated
+    Delta(usize),
+    /// A base85 data line, or the empty line between the forward and reverse data
+    Data,
+}
+
+/// The line range of one side of a hunk (`-start,count` or `+start,count`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HunkRange {
+    pub start: usize,
+    pub count: usize,
 }
 
 /// An enum representing the state of the parser
 ///
-/// `Text` progresses into `Diff` when a diff header is found,
+/// `Text` progresses into `Diff` when a diff header is found. Besides `diff --git`,
+/// this is a `diff -up` command line, an `Index:` line (`svn diff`, `quilt`), or a
+/// `---`/`+++` file header pair of a plain unified diff. The command line and the
+/// `Index:` line only count if such a pair follows them.
 /// `Diff` progresses into `Hunk` when a hunk header is found,
 /// and `Hunk` progresses into `Code` right after the hunk header line.
+/// `Text` also progresses into `Stat` on the `---` separator of a patch email,
+/// and into `Signature` on the `-- ` signature delimiter, which is never left.
+/// `Diff` goes back to `Text` on the first line that is not part of the diff metadata
+/// (e.g. an empty line or the signature after a diff without hunks), which is parsed
+/// in the `Text` state.
+/// `Stat` behaves like `Text`, but recognizes the diffstat lines as well.
 /// `Diff` state also includes the diff metadata lines.
+/// `Diff` progresses into `Binary` on `GIT binary patch`, which covers the `literal`/`delta`
+/// blocks of base85 data. Any other line ends it, and is parsed in the `Text` state.
 /// When in `Code` state, it checks for hunk headers again, which will reset the state to `Hunk`.
-/// Currently, there are no other ways to break out of the `Code` state.
+/// Combined diffs (`diff --cc`) of merges work the same, with one range per parent
+/// in their `@@@` hunk headers and one marker column per parent in their code lines.
+/// The `Code` state also counts down the lines announced by the hunk header, and returns
+/// to `Text` once both sides are exhausted. Lines that do not fit the counts are flagged
+/// with the `Damage` a mail client did to them, and so is the hunk header of their hunk.
+/// A line without a diff marker ends the hunk as truncated and is parsed in the `Text`
+/// state, unless it continues a wrapped line. A `Text` layer that has already seen a diff
+/// accepts further hunk headers, so consecutive hunks of the same file keep working.
+/// A quoted `Text` layer also picks up the hunk of a neighbouring layer, when a reviewer
+/// re-quoted the rest of the hunk one level deeper or shallower (see `continued_hunk_layer`).
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum State {
     Text,
+    Stat,
+    Signature,
     Diff,
     Hunk,
     Code,
+    Binary,
 }
 
 /// Maintains parsing state for a specific quoting layer
@@ -30,146 +181,401 @@ enum State {
 /// of the others.
 struct LayerState {
     state: State,
+    /// Path of the file the hunks apply to (the new path, unless the file is deleted)
     file_path: String,
+    /// Index of the `DiffHeader` line of the current file in the parsed lines
+    diff_header: Option<usize>,
+    /// Index of the `HunkHeader` line of the current hunk in the parsed lines
+    hunk_header: Option<usize>,
     language: String,
+    function_context: String,
+    /// Number of parents of the current hunk, more than one for combined diffs
+    parents: usize,
+    /// Lines left in the current hunk (old side of each parent, new side), `None` if unknown
+    remaining: Option<(Vec<usize>, usize)>,
+    /// Quoting layer the current hunk was started in, if it was taken over from another layer
+    hunk_origin: Option<usize>,
+}
+
+impl LayerState {
+    fn new() -> LayerState {
+        LayerState {
+            state: State::Text,
+            file_path: String::new(),
+            diff_header: None,
+            hunk_header: None,
+            language: language::UNKNOWN.to_string(),
+            function_context: String::new(),
+            parents: 1,
+            remaining: None,
+            hunk_origin: None,
+        }
+    }
+
+    /// Take over the hunk in progress of another layer, which goes back to `Text`
+    fn take_hunk(&mut self, layer: usize, from: &mut LayerState, from_layer: usize) {
+        self.state = State::Code;
+        self.file_path = from.file_path.clone();
+        self.diff_header = from.diff_header;
+        self.hunk_header = from.hunk_header;
+        self.language = from.language.clone();
+        self.function_context = from.function_context.clone();
+        self.parents = from.parents;
+        self.remaining = from.remaining.take();
+        let origin = from.hunk_origin.unwrap_or(from_layer);
+        self.hunk_origin = (origin != layer).then_some(origin);
+        from.state = State::Text;
+    }
+}
+
+/// The fields every line variant has, while a line is being parsed
+#[derive(Clone, Copy)]
+struct Position<'a> {
+    offset: usize,
+    length: usize,
+    quoting_layer: usize,
+    quote_prefix: &'a str,
+    raw: &'a str,
 }
 
 #[derive(Debug, Clone)]
 pub enum Line<'a> {
+    Header {
+        offset: usize,
+        length: usize,
+        quoting_layer: usize,
+        quote_prefix: &'a str,
+        raw: &'a str,
+    },
     Text {
         offset: usize,
         length: usize,
         quoting_layer: usize,
+        quote_prefix: &'a str,
+        raw: &'a str,
+    },
+    Separator {
+        offset: usize,
+        length: usize,
+        quoting_layer: usize,
+        quote_prefix: &'a str,
+        raw: &'a str,
+    },
+    DiffStat {
+        offset: usize,
+        length: usize,
+        quoting_layer: usize,
+        quote_prefix: &'a str,
+        raw: &'a str,
+    },
+    Signature {
+        offset: usize,
+        length: usize,
+        quoting_layer: usize,
+        quote_prefix: &'a str,
         raw: &'a str,
     },
     DiffHeader {
         offset: usize,
         length: usize,
         quoting_layer: usize,
-        file_path: String,
+        quote_prefix: &'a str,
+        /// Path of the file before the change, `/dev/null` if it is created
+        old_path: String,
+        /// Path of the file after the change, `/dev/null` if it is deleted
+        new_path: String,
         raw: &'a str,
     },
     DiffMetadata {
         offset: usize,
         length: usize,
         quoting_layer: usize,
+        quote_prefix: &'a str,
+        kind: MetadataKind,
         raw: &'a str,
     },
     HunkHeader {
         offset: usize,
         length: usize,
         quoting_layer: usize,
+        quote_prefix: &'a str,
+        #[cfg(debug_assertions)]
         file_path: String,
+        #[cfg(debug_assertions)]
         language: String,
+        /// One range per parent, a single one unless it is a combined diff
+        #[cfg(debug_assertions)]
+        old_ranges: Vec<HunkRange>,
+        #[cfg(debug_assertions)]
+        new_range: HunkRange,
+        #[cfg(debug_assertions)]
+        function_context: String,
+        /// The first damage found in the hunk, if any
+        damage: Option<Damage>,
+        raw: &'a str,
+    },
+    BinaryPatch {
+        offset: usize,
+        length: usize,
+        quoting_layer: usize,
+        quote_prefix: &'a str,
+        kind: BinaryKind,
         raw: &'a str,
     },
     Code {
         offset: usize,
         length: usize,
         quoting_layer: usize,
+        quote_prefix: &'a str,
         kind: CodeKind,
+        #[cfg(debug_assertions)]
         file_path: String,
         language: String,
+        function_context: String,
+        damage: Option<Damage>,
+        /// The quoting layer the hunk was started in, if the line continues it in another layer
+        #[cfg(debug_assertions)]
+        continued_from: Option<usize>,
         raw: &'a str,
     },
 }
 
-impl Line<'_> {
+impl<'a> Line<'a> {
     /// Parse all lines from the given source code
     ///
-    /// Each line has the fields `offset`, `length`, `quoting_layer`, and `raw`.
-    /// On top of that, `DiffHeader`, `HunkHeader`, and `Code` lines
-    /// have the additional field `file_path`.
-    /// `HunkHeader` and `Code` lines also have the field `language`.
+    /// The email header block (see `Headers::parse`) is split into `Header` lines first,
+    /// the rest of the source goes through the state machine.
+    /// Each line has the fields `offset`, `length`, `quoting_layer`, `quote_prefix`, and `raw`.
+    /// The quote prefix is recognized according to the given `QuoteProfile`.
+    /// On top of that, `HunkHeader` and `Code` lines have the additional field `file_path`
+    /// (only kept in debug builds, like the `language` and `function_context` of `HunkHeader` lines),
+    /// and `DiffHeader` lines have the `old_path` and `new_path` of the file. Those are
+    /// refined by the metadata following the header (`rename to`, `+++`, etc.).
+    /// `DiffMetadata` lines have the `kind` of the extended header line (see `MetadataKind`),
+    /// and `BinaryPatch` lines the `kind` of the line in the binary patch (see `BinaryKind`).
+    /// `HunkHeader` and `Code` lines have a `damage` field for patches mangled by mail clients.
+    /// `HunkHeader` and `Code` lines also have the fields `language` and `function_context`,
+    /// the latter being the enclosing function signature git puts after the hunk ranges.
+    /// `HunkHeader` lines carry the parsed `old_ranges` and `new_range` as well, in debug builds.
+    /// `Code` lines that continue a hunk of another quoting layer have that layer in
+    /// `continued_from`, in debug builds.
     /// The `kind` field in `Code` lines indicates whether the line is an addition (`+`), a removal (`-`), or context (no sign)
-    /// based on the diff format.
-    pub fn parse_lines(source: &str) -> Vec<Line> {
+    /// based on the diff format. Lines of combined diffs carry one such marker per parent.
+    pub fn parse_lines(source: &'a str, profile: QuoteProfile) -> Vec<Line<'a>> {
         let mut lines = Vec::new();
         let mut offset = 0usize;
 
         let mut layers: Vec<Option<LayerState>> = Vec::new();
+        let headers_end = Headers::parse(source).end;
 
-        for raw in source.lines() {
-            let len = raw.len() + 1;
-            let ql = quoting_layer(raw);
-            let line = raw.trim_start_matches('>');
-            let trimmed = line.trim_start();
+        let mut chunks = source.split_inclusive('\n');
+
+        while let Some(chunk) = chunks.next() {
+            /* Keep the line terminator out of `raw`, but account for it in the offsets */
+            let raw = chunk.strip_suffix('\n').unwrap_or(chunk);
+            let raw = raw.strip_suffix('\r').unwrap_or(raw);
+            let len = chunk.len();
+
+            if offset < headers_end {
+                lines.push(Line::Header {
+                    offset,
+                    length: len,
+                    quoting_layer: 0,
+                    quote_prefix: "",
+                    raw,
+                });
+                offset += len;
+                continue;
+            }
+
+            let (ql, quote_prefix, content) = profile.split(raw);
+            let trimmed = content.trim_start();
+            let at = Position {
+                offset,
+                length: len,
+                quoting_layer: ql,
+                quote_prefix,
+                raw,
+            };
+            /* Some lines are only recognized together with the lines that follow */
+            let following = || {
+                chunks
+                    .clone()
+                    .map(|chunk| profile.split(chunk.trim_end_matches(['\n', '\r'])))
+            };
+            let next = following().next();
+            let next_content = next.map(|(_, _, content)| content);
 
             /* Dynamically resize layers vector for infinite quoting layers */
             if ql >= layers.len() {
                 layers.resize_with(ql + 1, || None);
             }
 
-            let entry = layers[ql].get_or_insert_with(|| LayerState {
-                state: State::Text,
-                file_path: String::new(),
-                language: "Unknown".to_string(),
-            });
+            /* A reviewer may have re-quoted the rest of a hunk at another depth */
+            if let Some(from) = continued_hunk_layer(&layers, &lines, ql, content) {
+                let mut from_entry = layers[from].take().unwrap_or_else(LayerState::new);
+                layers[ql]
+                    .get_or_insert_with(LayerState::new)
+                    .take_hunk(ql, &mut from_entry, from);
+                layers[from] = Some(from_entry);
+            }
+
+            let entry = layers[ql].get_or_insert_with(LayerState::new);
+
+            /* The metadata ends with the first line that is not part of it */
+            if entry.state == State::Diff && !is_diff_metadata(trimmed, following(), ql) {
+                entry.state = State::Text;
+            }
+            /* A binary patch ends with the first line that is not part of it */
+            if entry.state == State::Binary && match_binary_kind(trimmed, next_content).is_none() {
+                entry.state = State::Text;
+            }
+
+            /* A line without a diff marker ends a hunk the replier trimmed, unless it was wrapped */
+            if matches!(entry.state, State::Hunk | State::Code)
+                && entry.parents <= 1
+                && content.starts_with(|c: char| !matches!(c, '+' | '-' | ' ' | '\u{a0}' | '\t'))
+                && !content.starts_with("@@")
+                && no_newline_kind(&lines, ql, content).is_none()
+                && !continues_wrapped_line(&lines, ql, content)
+            {
+                end_hunk(entry, &mut lines);
+                entry.state = State::Text;
+            }
 
             match entry.state {
-                State::Text => {
-                    if trimmed.starts_with("diff --git") {
-                        entry.state = State::Diff;
-                        entry.file_path = extract_file_path(trimmed);
-                        entry.language = detect_language(&entry.file_path);
-                        lines.push(Line::DiffHeader {
+                State::Signature => {
+                    lines.push(Line::Signature {
+                        offset,
+                        length: len,
+                        quoting_layer: ql,
+                        quote_prefix,
+                        raw,
+                    });
+                }
+                State::Text | State::Stat => {
+                    /* A plain unified diff starts right at its `---`/`+++` pair */
+                    let diff_paths = diff_start_paths(trimmed, following(), ql)
+                        .or_else(|| file_header_pair_paths(trimmed, next, ql));
+
+                    /* The last line of a hunk can be followed by the no newline marker */
+                    if let Some(kind) = no_newline_kind(&lines, ql, trimmed) {
+                        lines.push(code_line(entry, kind, None, at));
+                    } else if let Some(paths) = diff_paths {
+                        let line = diff_header(entry, lines.len(), paths, at);
+                        lines.push(line);
+                    } else if !entry.file_path.is_empty() && parse_hunk_header(trimmed).is_some() {
+                        let line = hunk_header(entry, lines.len(), trimmed, at);
+                        lines.push(line);
+                    } else if entry.state == State::Stat && is_diffstat(trimmed) {
+                        lines.push(Line::DiffStat {
                             offset,
                             length: len,
                             quoting_layer: ql,
-                            file_path: entry.file_path.clone(),
+                            quote_prefix,
                             raw,
                         });
-                    } else {
-                        lines.push(Line::Text {
+                    } else if is_separator(trimmed) {
+                        if trimmed.trim_end() == "---" {
+                            entry.state = State::Stat;
+                        }
+                        lines.push(Line::Separator {
                             offset,
                             length: len,
                             quoting_layer: ql,
+                            quote_prefix,
                             raw,
                         });
-                    }
-                }
-                State::Diff => {
-                    if trimmed.starts_with("@@") {
-                        entry.state = State::Hunk;
-                        lines.push(Line::HunkHeader {
+                    } else if is_signature_delimiter(trimmed) {
+                        entry.state = State::Signature;
+                        lines.push(Line::Signature {
                             offset,
                             length: len,
                             quoting_layer: ql,
-                            file_path: entry.file_path.clone(),
-                            language: entry.language.clone(),
+                            quote_prefix,
                             raw,
                         });
                     } else {
-                        lines.push(Line::DiffMetadata {
+                        lines.push(Line::Text {
                             offset,
                             length: len,
                             quoting_layer: ql,
+                            quote_prefix,
                             raw,
                         });
                     }
                 }
-                State::Hunk | State::Code => {
+                State::Diff => {
                     if trimmed.starts_with("@@") {
-                        entry.state = State::Hunk;
-                        lines.push(Line::HunkHeader {
+                        let line = hunk_header(entry, lines.len(), trimmed, at);
+                        lines.push(line);
+                    } else if let Some(paths) = diff_start_paths(trimmed, following(), ql) {
+                        /* The previous file had no hunks (binary, rename, mode change) */
+                        let line = diff_header(entry, lines.len(), paths, at);
+                        lines.push(line);
+                    } else {
+                        if let Some((side, path)) = path::metadata_path(trimmed) {
+                            update_diff_paths(entry, &mut lines, side, path);
+                        }
+                        let kind = match_metadata_kind(trimmed);
+                        if kind == MetadataKind::GitBinaryPatch {
+                            entry.state = State::Binary;
+                        }
+                        lines.push(Line::DiffMetadata {
                             offset,
                             length: len,
                             quoting_layer: ql,
-                            file_path: entry.file_path.clone(),
-                            language: entry.language.clone(),
+                            quote_prefix,
+                            kind,
                             raw,
                         });
+                    }
+                }
+                State::Binary => {
+                    lines.push(Line::BinaryPatch {
+                        offset,
+                        length: len,
+                        quoting_layer: ql,
+                        quote_prefix,
+                        kind: match_binary_kind(trimmed, next_content).unwrap_or(BinaryKind::Data),
+                        raw,
+                    });
+                }
+                State::Hunk | State::Code => {
+                    /* The diff marker columns come right after the quote prefix */
+                    let columns = content;
+
+                    if trimmed.starts_with("@@") {
+                        end_hunk(entry, &mut lines);
+                        let line = hunk_header(entry, lines.len(), trimmed, at);
+                        lines.push(line);
+                    } else if let Some(paths) = diff_start_paths(columns, following(), ql) {
+                        end_hunk(entry, &mut lines);
+                        let line = diff_header(entry, lines.len(), paths, at);
+                        lines.push(line);
                     } else {
+                        let (kind, damage) = if let Some(kind) =
+                            no_newline_kind(&lines, ql, trimmed)
+                        {
+                            (kind, None)
+                        } else if entry.parents > 1 {
+                            (match_combined_code_kind(columns, entry.parents), None)
+                        } else if columns.is_empty() && expects_context_line(entry) {
+                            /* A blank context line whose marker was trimmed, but still fits the counts */
+                            (CodeKind::Context, None)
+                        } else {
+                            match_code_kind(columns, previous_code_kind(&lines, ql))
+                        };
+
                         entry.state = State::Code;
-                        lines.push(Line::Code {
-                            offset,
-                            length: len,
-                            quoting_layer: ql,
-                            kind: match_code_kind(trimmed).unwrap(),
-                            file_path: entry.file_path.clone(),
-                            language: entry.language.clone(),
-                            raw,
-                        });
+                        if let Some(damage) = damage {
+                            mark_hunk_damaged(entry, &mut lines, damage);
+                        }
+                        /* A wrapped line is part of the line before it */
+                        if damage != Some(Damage::Wrapped) {
+                            consume_hunk_line(entry, &kind);
+                        }
+                        lines.push(code_line(entry, kind, damage, at));
                     }
                 }
             }
@@ -177,42 +583,152 @@ impl Line<'_> {
             offset += len;
         }
 
+        for entry in layers.iter_mut().flatten() {
+            if matches!(entry.state, State::Hunk | State::Code) {
+                end_hunk(entry, &mut lines);
+            }
+        }
+
         lines
     }
 
     /// Get the raw line text
     pub fn get_raw(&self) -> &str {
         match self {
+            Line::Header { raw, .. } => raw,
             Line::Text { raw, .. } => raw,
+            Line::Separator { raw, .. } => raw,
+            Line::DiffStat { raw, .. } => raw,
+            Line::Signature { raw, .. } => raw,
             Line::DiffHeader { raw, .. } => raw,
             Line::DiffMetadata { raw, .. } => raw,
             Line::HunkHeader { raw, .. } => raw,
+            Line::BinaryPatch { raw, .. } => raw,
             Line::Code { raw, .. } => raw,
         }
     }
 
+    /// Split a line into its quote prefix and the rest
+    pub fn split_quote(&self) -> (&'a str, &'a str) {
+        let (quote_prefix, raw): (&'a str, &'a str) = match self {
+            Line::Header {
+                quote_prefix, raw, ..
+            }
+            | Line::Text {
+                quote_prefix, raw, ..
+            }
+            | Line::Separator {
+                quote_prefix, raw, ..
+            }
+            | Line::DiffStat {
+                quote_prefix, raw, ..
+            }
+            | Line::Signature {
+                quote_prefix, raw, ..
+            }
+            | Line::DiffHeader {
+                quote_prefix, raw, ..
+            }
+            | Line::DiffMetadata {
+                quote_prefix, raw, ..
+            }
+            | Line::HunkHeader {
+                quote_prefix, raw, ..
+            }
+            | Line::BinaryPatch {
+                quote_prefix, raw, ..
+            }
+            | Line::Code {
+                quote_prefix, raw, ..
+            } => (quote_prefix, raw),
+        };
+        raw.split_at(quote_prefix.len())
+    }
+
+    /// Split a `Code` line into its quote prefix, diff marker, and source code
+    ///
+    /// The quote prefix contains the quote marks of the quoting layer (and the
+    /// whitespace in between), plus a single space following the last mark
+    /// (see `QuoteProfile::split`).
+    /// The diff marker is the `+`, `-`, or ` ` column of the diff (one column per
+    /// parent for combined diffs). It is empty if the line lost it (e.g. trailing
+    /// whitespace was stripped by a mail client).
+    /// Returns `None` for non-`Code` lines.
+    pub fn split_code(&self) -> Option<(&'a str, &'a str, &'a str)> {
+        let Line::Code { kind, .. } = self else {
+            return None;
+        };
+
+        let (prefix, rest) = self.split_quote();
+        let columns = match kind {
+            CodeKind::Combined(markers) => markers.len(),
+            _ => 1,
+        };
+        let marker_len = rest
+            .bytes()
+            .take(columns)
+            .take_while(|b| matches!(b, b'+' | b'-' | b' '))
+            .count();
+        let (marker, code) = rest.split_at(marker_len);
+
+        Some((prefix, marker, code))
+    }
+
     /// Get the quoting layer of the line
     pub fn get_quoting_layer(&self) -> usize {
         match self {
-            Line::Text { quoting_layer, .. }
+            Line::Header { quoting_layer, .. }
+            | Line::Text { quoting_layer, .. }
+            | Line::Separator { quoting_layer, .. }
+            | Line::DiffStat { quoting_layer, .. }
+            | Line::Signature { quoting_layer, .. }
             | Line::DiffHeader { quoting_layer, .. }
             | Line::DiffMetadata { quoting_layer, .. }
             | Line::HunkHeader { quoting_layer, .. }
+            | Line::BinaryPatch { quoting_layer, .. }
             | Line::Code { quoting_layer, .. } => *quoting_layer,
         }
     }
 
-    /// Get the end offset of the content
-    pub fn get_end_offset(&self) -> usize {
+    /// Get the start offset of the line
+    pub fn get_offset(&self) -> usize {
         match self {
-            Line::Text { offset, length, .. } => offset + length - 1,
-            Line::DiffHeader { offset, length, .. } => offset + length - 1,
-            Line::DiffMetadata { offset, length, .. } => offset + length - 1,
-            Line::HunkHeader { offset, length, .. } => offset + length - 1,
-            Line::Code { offset, length, .. } => offset + length - 1,
+            Line::Header { offset, .. }
+            | Line::Text { offset, .. }
+            | Line::Separator { offset, .. }
+            | Line::DiffStat { offset, .. }
+            | Line::Signature { offset, .. }
+            | Line::DiffHeader { offset, .. }
+            | Line::DiffMetadata { offset, .. }
+            | Line::HunkHeader { offset, .. }
+            | Line::BinaryPatch { offset, .. }
+            | Line::Code { offset, .. } => *offset,
         }
     }
 
+    /// Get the length of the line, including the line terminator
+    pub fn get_length(&self) -> usize {
+        match self {
+            Line::Header { length, .. }
+            | Line::Text { length, .. }
+            | Line::Separator { length, .. }
+            | Line::DiffStat { length, .. }
+            | Line::Signature { length, .. }
+            | Line::DiffHeader { length, .. }
+            | Line::DiffMetadata { length, .. }
+            | Line::HunkHeader { length, .. }
+            | Line::BinaryPatch { length, .. }
+            | Line::Code { length, .. } => *length,
+        }
+    }
+
+    /// Get the end offset of the content
+    ///
+    /// The line terminator (`\n` or `\r\n`) is not part of the content.
+    pub fn get_end_offset(&self) -> usize {
+        self.get_offset() + self.get_raw().len()
+    }
+
     /// Check if two lines belong to the same quoting layer
     #[inline]
     pub fn same_quoting_layer(&self, other: &Self) -> bool {
@@ -224,78 +740,856 @@ impl Line<'_> {
         use Line::*;
         matches!(
             (self, other),
-            (Text { .. }, Text { .. })
+            (Header { .. }, Header { .. })
+                | (Text { .. }, Text { .. })
+                | (Separator { .. }, Separator { .. })
+                | (DiffStat { .. }, DiffStat { .. })
+                | (Signature { .. }, Signature { .. })
                 | (DiffHeader { .. }, DiffHeader { .. })
                 | (DiffMetadata { .. }, DiffMetadata { .. })
                 | (HunkHeader { .. }, HunkHeader { .. })
+                | (BinaryPatch { .. }, BinaryPatch { .. })
                 | (Code { .. }, Code { .. })
         )
     }
 }
 
-/// Get the current quoting layer of a line
+/// Build a `DiffHeader` line and start a new file diff in the layer
 ///
-/// Every `>` followed by a whitespace or another `>` counts as a layer.
-/// In case the `>` is followed by a non-whitespace character, it is not counted.
-/// This is to counter edge cases where symbols as `->` are splitted across lines.
+/// `index` is the index the line will have in the parsed lines, so the
+/// metadata can update its paths later on (see `update_diff_paths`).
+fn diff_header<'a>(
+    entry: &mut LayerState,
+    index: usize,
+    (old_path, new_path): (String, String),
+    at: Position<'a>,
+) -> Line<'a> {
+    entry.state = State::Diff;
+    entry.diff_header = Some(index);
+    set_file_path(entry, &old_path, &new_path);
+
+    Line::DiffHeader {
+        offset: at.offset,
+        length: at.length,
+        quoting_layer: at.quoting_layer,
+        quote_prefix: at.quote_prefix,
+        old_path,
+        new_path,
+        raw: at.raw,
+    }
+}
+
+/// Set the file path (and the language) of the layer from the paths of a diff
 ///
-/// I doubt that this is a perfect solution, but it works for now.
-#[inline]
-fn quoting_layer(line: &str) -> usize {
-    let mut count = 0;
-    let mut seen_non_ws = false;
-
-    for c in line.chars() {
-        match c {
-            '>' => {
-                if seen_non_ws {
-                    break;
-                }
-                count += 1;
-            }
-            ' ' | '\t' => continue,
-            _ => break,
+/// This is the new path, unless the file is deleted.
+fn set_file_path(entry: &mut LayerState, old_path: &str, new_path: &str) {
+    entry.file_path = if new_path == DEV_NULL {
+        old_path
+    } else {
+        new_path
+    }
+    .to_string();
+    entry.language = language::detect(&entry.file_path)
+        .map_or(language::UNKNOWN, |l| l.id)
+        .to_string();
+}
+
+/// Update a path of the current `DiffHeader` from a metadata line
+///
+/// The metadata (`rename to`, `+++ b/...`) is more reliable than the paths
+/// guessed from the header line, which is why the header is updated after
+/// the fact, and so is the file path of the layer.
+fn update_diff_paths(entry: &mut LayerState, lines: &mut [Line], side: PathSide, path: String) {
+    let Some(Line::DiffHeader {
+        old_path, new_path, ..
+    }) = entry.diff_header.and_then(|i| lines.get_mut(i))
+    else {
+        return;
+    };
+
+    match side {
+        PathSide::Old => *old_path = path,
+        PathSide::New => *new_path = path,
+    }
+    let (old_path, new_path) = (old_path.clone(), new_path.clone());
+    set_file_path(entry, &old_path, &new_path);
+}
+
+/// Build a `HunkHeader` line and start a new hunk in the layer
+///
+/// The layer remembers the function context and the line counts of the hunk.
+/// If the header cannot be parsed, the counts are unknown and the hunk only
+/// ends at the next hunk header.
+fn hunk_header<'a>(
+    entry: &mut LayerState,
+    index: usize,
+    trimmed: &str,
+    at: Position<'a>,
+) -> Line<'a> {
+    let parsed = parse_hunk_header(trimmed);
+    let counts_known = parsed.is_some();
+    let (old_ranges, new_range, function_context) =
+        parsed.unwrap_or_else(|| (vec![HunkRange::default()], HunkRange::default(), ""));
+
+    entry.remaining = counts_known.then(|| {
+        (
+            old_ranges.iter().map(|r| r.count).collect(),
+            new_range.count,
+        )
+    });
+
+    entry.function_context = function_context.to_string();
+    entry.parents = old_ranges.len();
+    entry.hunk_header = Some(index);
+    entry.hunk_origin = None;
+    entry.state = match &entry.remaining {
+        Some((old, 0)) if old.iter().all(|&c| c == 0) => State::Text,
+        _ => State::Hunk,
+    };
+
+    Line::HunkHeader {
+        offset: at.offset,
+        length: at.length,
+        quoting_layer: at.quoting_layer,
+        quote_prefix: at.quote_prefix,
+        #[cfg(debug_assertions)]
+        file_path: entry.file_path.clone(),
+        #[cfg(debug_assertions)]
+        language: entry.language.clone(),
+        #[cfg(debug_assertions)]
+        old_ranges,
+        #[cfg(debug_assertions)]
+        new_range,
+        #[cfg(debug_assertions)]
+        function_context: entry.function_context.clone(),
+        damage: None,
+        raw: at.raw,
+    }
+}
+
+/// Flag the current hunk as truncated if it ends before its counts are exhausted
+fn end_hunk(entry: &mut LayerState, lines: &mut [Line]) {
+    let truncated = entry
+        .remaining
+        .as_ref()
+        .is_some_and(|(old, new)| *new > 0 || old.iter().any(|&c| c > 0));
+    if truncated {
+        mark_hunk_damaged(entry, lines, Damage::Truncated);
+    }
+}
+
+/// Flag the `HunkHeader` of the current hunk with the first damage found in the hunk
+fn mark_hunk_damaged(entry: &LayerState, lines: &mut [Line], found: Damage) {
+    if let Some(Line::HunkHeader { damage, .. }) = entry.hunk_header.and_then(|i| lines.get_mut(i))
+    {
+        damage.get_or_insert(found);
+    }
+}
+
+/// Build a `Code` line of the current hunk of the layer
+fn code_line<'a>(
+    entry: &LayerState,
+    kind: CodeKind,
+    damage: Option<Damage>,
+    at: Position<'a>,
+) -> Line<'a> {
+    Line::Code {
+        offset: at.offset,
+        length: at.length,
+        quoting_layer: at.quoting_layer,
+        quote_prefix: at.quote_prefix,
+        kind,
+        #[cfg(debug_assertions)]
+        file_path: entry.file_path.clone(),
+        language: entry.language.clone(),
+        function_context: entry.function_context.clone(),
+        damage,
+        #[cfg(debug_assertions)]
+        continued_from: entry.hunk_origin,
+        raw: at.raw,
+    }
+}
+
+/// Get the neighbouring quoting layer whose hunk a line continues, if any
+///
+/// This is the case for a quoted line of a layer without a hunk of its own,
+/// right after the hunk of the layer one level deeper or shallower (only
+/// comments in between), if its diff marker fits the lines left in that hunk.
+/// Unquoted lines are the reviewer's own words and never continue a hunk.
+fn continued_hunk_layer(
+    layers: &[Option<LayerState>],
+    lines: &[Line],
+    quoting_layer: usize,
+    content: &str,
+) -> Option<usize> {
+    if quoting_layer == 0
+        || layers[quoting_layer]
+            .as_ref()
+            .is_some_and(|l| !matches!(l.state, State::Text | State::Stat))
+        || is_separator(content)
+    {
+        return None;
+    }
+
+    /* The most recent diff line must belong to a neighbouring layer */
+    let from = lines
+        .iter()
+        .rev()
+        .find(|l| matches!(l, Line::Code { .. } | Line::HunkHeader { .. }))?
+        .get_quoting_layer();
+    if from.abs_diff(quoting_layer) != 1 {
+        return None;
+    }
+
+    let from_entry = layers.get(from)?.as_ref()?;
+    if !matches!(from_entry.state, State::Hunk | State::Code) {
+        return None;
+    }
+    let (old, new) = from_entry.remaining.as_ref()?;
+
+    let kind = if from_entry.parents > 1 {
+        let mut columns = content.bytes().take(from_entry.parents);
+        if columns.len() < from_entry.parents || !columns.all(|b| matches!(b, b'+' | b'-' | b' ')) {
+            return None;
         }
-        seen_non_ws = true;
+        match_combined_code_kind(content, from_entry.parents)
+    } else {
+        match match_code_kind(content, None) {
+            (kind, None) => kind,
+            _ => return None,
+        }
+    };
+    let fits = (!kind.in_new() || *new > 0)
+        && old
+            .iter()
+            .enumerate()
+            .all(|(parent, &count)| !kind.in_parent(parent) || count > 0);
+
+    fits.then_some(from)
+}
+
+/// Get the kind of the last line of a quoting layer, if it is a `Code` line
+fn previous_code_kind(lines: &[Line], quoting_layer: usize) -> Option<CodeKind> {
+    match lines
+        .iter()
+        .rev()
+        .find(|l| l.get_quoting_layer() == quoting_layer)?
+    {
+        Line::Code { kind, .. } => Some(kind.clone()),
+        _ => None,
     }
+}
 
-    count
+/// Check if a line without a diff marker continues the code line before it
+///
+/// The line before a continuation is long, or it ends in the middle of a word
+/// that the client cut. After a short, complete line, the line is not part of
+/// the hunk, e.g. the reply after a quoted hunk that was trimmed.
+fn continues_wrapped_line(lines: &[Line], quoting_layer: usize, line: &str) -> bool {
+    let Some(previous @ Line::Code { .. }) = lines
+        .iter()
+        .rev()
+        .find(|l| l.get_quoting_layer() == quoting_layer)
+    else {
+        return false;
+    };
+    let raw = previous.get_raw();
+    let in_word = |c: char| c.is_alphanumeric() || c == '_';
+    raw.chars().count() >= MIN_WRAP_WIDTH || (raw.ends_with(in_word) && line.starts_with(in_word))
 }
 
-/// Extract the file path from a diff line
+/// Get the kind of a `\ No newline at end of file` marker line
 ///
-/// This is quite volatile,but it works for common diff cases.
+/// The marker applies to the line right before it, so it is only recognized
+/// after a code line of the same quoting layer. Other diff tools translate
+/// the message, which is why only the leading `\ ` is checked.
+fn no_newline_kind(lines: &[Line], quoting_layer: usize, line: &str) -> Option<CodeKind> {
+    if !line.starts_with("\\ ") {
+        return None;
+    }
+    previous_code_kind(lines, quoting_layer).map(|kind| CodeKind::NoNewline(Box::new(kind)))
+}
+
+/// Check if the current hunk has lines left on both sides, so a context line fits in it
+fn expects_context_line(entry: &LayerState) -> bool {
+    entry
+        .remaining
+        .as_ref()
+        .is_some_and(|(old, new)| *new > 0 && old.iter().all(|&c| c > 0))
+}
+
+/// Count a code line against the remaining lines of the current hunk
+///
+/// Context lines belong to both sides, additions to the new side only,
+/// and removals to the old side only. Lines of combined diffs are counted
+/// against every parent they belong to (see `CodeKind::in_parent`).
+/// Once all sides are exhausted, the layer goes back to the `Text` state.
+fn consume_hunk_line(entry: &mut LayerState, kind: &CodeKind) {
+    let Some((old, new)) = entry.remaining.as_mut() else {
+        return;
+    };
+
+    for (parent, count) in old.iter_mut().enumerate() {
+        if kind.in_parent(parent) {
+            *count = count.saturating_sub(1);
+        }
+    }
+    if kind.in_new() {
+        *new = new.saturating_sub(1);
+    }
+
+    if *new == 0 && old.iter().all(|&c| c == 0) {
+        entry.state = State::Text;
+    }
+}
+
+/// Parse a hunk header (`@@ -a,b +c,d @@ context`)
+///
+/// Returns the old ranges, the new range, and the function context following
+/// the closing `@@` (empty if there is none). A missing count defaults to 1,
+/// as in `@@ -3 +3 @@`. Combined diffs have one `@` more than they have
+/// parents, and one old range per parent (`@@@ -a,b -c,d +e,f @@@`).
+fn parse_hunk_header(line: &str) -> Option<(Vec<HunkRange>, HunkRange, &str)> {
+    let marks = line.bytes().take_while(|&b| b == b'@').count();
+    if marks < 2 {
+        return None;
+    }
+    let rest = line[marks..].strip_prefix(' ')?;
+    let (ranges, context) = rest.split_once(&line[..marks])?;
+
+    let mut ranges = ranges.split_whitespace();
+    let old_ranges = (1..marks)
+        .map(|_| parse_hunk_range(ranges.next()?.strip_prefix('-')?))
+        .collect::<Option<Vec<_>>>()?;
+    let new_range = parse_hunk_range(ranges.next()?.strip_prefix('+')?)?;
+
+    Some((old_ranges, new_range, context.trim()))
+}
+
+/// Parse a `start,count` or `start` range of a hunk header
+fn parse_hunk_range(range: &str) -> Option<HunkRange> {
+    let (start, count) = match range.split_once(',') {
+        Some((start, count)) => (start.parse().ok()?, count.parse().ok()?),
+        None => (range.parse().ok()?, 1),
+    };
+    Some(HunkRange { start, count })
+}
+
+/// Check if a line separates parts of a patch email
+///
+/// This is either the `---` line between the commit message and the diffstat,
+/// or a scissors line (`-- >8 --`) used by `git am --scissors`.
+#[inline]
+fn is_separator(line: &str) -> bool {
+    let line = line.trim_end();
+    line == "---"
+        || ((line.contains(">8") || line.contains("8<"))
+            && line
+                .chars()
+                .all(|c| matches!(c, '-' | '>' | '<' | '8' | ' ')))
+}
+
+/// Check if a line is the `-- ` delimiter in front of the signature
+///
+/// Mail clients often strip the trailing space, so a bare `--` counts too.
+#[inline]
+fn is_signature_delimiter(line: &str) -> bool {
+    line.trim_end() == "--"
+}
+
+/// Check if a line is part of a diffstat
+///
+/// Recognizes file entries (`path | 12 ++--`, `path | Bin 0 -> 12 bytes`),
+/// the summary (`3 files changed, 5 insertions(+)`), and the summary of
+/// created, deleted, renamed, and mode changed files.
+#[inline]
+fn is_diffstat(line: &str) -> bool {
+    let line = line.trim();
+
+    if let Some((path, bar)) = line.rsplit_once(" | ") {
+        let bar = bar.trim();
+        let count_end = bar.find(|c: char| !c.is_ascii_digit()).unwrap_or(bar.len());
+        return !path.trim().is_empty()
+            && (bar.starts_with("Bin ")
+                || (count_end > 0
+                    && bar[count_end..]
+                        .trim()
+                        .chars()
+                        .all(|c| c == '+' || c == '-')));
+    }
+
+    let count_end = line.find(|c: char| !c.is_ascii_digit()).unwrap_or(0);
+    (count_end > 0
+        && (line[count_end..].starts_with(" file changed")
+            || line[count_end..].starts_with(" files changed")))
+        || line.starts_with("create mode ")
+        || line.starts_with("delete mode ")
+        || line.starts_with("mode change ")
+        || ((line.starts_with("rename ") || line.starts_with("copy ")) && line.ends_with("%)"))
+}
+
+/// Get the old and new path of a line that starts a diff
+///
+/// These are `diff --git a/x b/x` (and `diff --cc x`), the `diff -up old new`
+/// command line of a plain diff, and the `Index: path` line of `svn diff` and
+/// `quilt`. The latter two could just as well start a sentence, so they only
+/// start a diff if a `---`/`+++` file header pair follows them (see
+/// `file_header_pair_follows`). Returns `None` for any other line.
+fn diff_start_paths<'s>(
+    line: &str,
+    following: impl Iterator<Item = (usize, &'s str, &'s str)>,
+    quoting_layer: usize,
+) -> Option<(String, String)> {
+    if line.starts_with("diff --git ")
+        || line.starts_with("diff --cc ")
+        || line.starts_with("diff --combined ")
+    {
+        path::git_diff_paths(line)
+    } else if line.starts_with("diff -") {
+        file_header_pair_follows(following, quoting_layer)
+            .then(|| path::diff_command_paths(line))?
+    } else {
+        let path = line.strip_prefix("Index: ")?.trim();
+        (!path.is_empty() && file_header_pair_follows(following, quoting_layer))
+            .then(|| (path.to_string(), path.to_string()))
+    }
+}
+
+/// Check if the following lines of a quoting layer start with a `---`/`+++` file header pair
+///
+/// The `====` rule that `svn diff` and `quilt` put in front of the pair is skipped.
+fn file_header_pair_follows<'s>(
+    following: impl Iterator<Item = (usize, &'s str, &'s str)>,
+    quoting_layer: usize,
+) -> bool {
+    let mut headers = following
+        .skip_while(|&(layer, _, content)| layer == quoting_layer && is_rule(content))
+        .map(|(layer, _, content)| (layer == quoting_layer).then(|| content.trim_start()));
+
+    matches!(
+        (headers.next(), headers.next()),
+        (Some(Some(old)), Some(Some(new))) if old.starts_with("--- ") && new.starts_with("+++ ")
+    )
+}
+
+/// Check if a line is a `====` rule, as `svn diff` puts under its `Index:` line
 #[inline]
-fn extract_file_path(diff_line: &str) -> String {
-    diff_line
-        .split_whitespace()
-        .nth(2)
-        .unwrap_or("unknown")
-        .trim_start_matches("a/")
-        .to_string()
+fn is_rule(line: &str) -> bool {
+    let line = line.trim_end();
+    !line.is_empty() && line.bytes().all(|b| b == b'=')
+}
+
+/// Check if a line is part of the metadata of a diff, in between its header and its first hunk
+///
+/// These are the extended header lines (see `MetadataKind`), including the
+/// `mode` line of combined diffs, the `====` rule of `svn diff`, and the
+/// hunk header or the header of the next diff that end the metadata.
+fn is_diff_metadata<'s>(
+    line: &str,
+    following: impl Iterator<Item = (usize, &'s str, &'s str)>,
+    quoting_layer: usize,
+) -> bool {
+    line.starts_with("@@")
+        || line.starts_with("mode ")
+        || is_rule(line)
+        || match_metadata_kind(line) != MetadataKind::Unknown
+        || diff_start_paths(line, following, quoting_layer).is_some()
+}
+
+/// Get the old and new path of a `---`/`+++` file header pair of a plain unified diff
+///
+/// The `---` line of the pair is recognized by the `+++` line that follows it
+/// on the same quoting layer, which keeps it apart from the `---` separator of
+/// a patch email.
+fn file_header_pair_paths(
+    line: &str,
+    next: Option<(usize, &str, &str)>,
+    quoting_layer: usize,
+) -> Option<(String, String)> {
+    let old = line.strip_prefix("--- ")?;
+    let (next_layer, _, next) = next?;
+    if next_layer != quoting_layer {
+        return None;
+    }
+    let new = next.trim_start().strip_prefix("+++ ")?;
+
+    Some((path::file_header_path(old), path::file_header_path(new)))
 }
 
-/// Detect the language based on the file extension
+/// Get the code kind of a combined diff line, one marker column per parent
+///
+/// A column that is neither `+` nor `-` (including a missing one, e.g. after
+/// a mail client stripped trailing whitespace) counts as context.
 #[inline]
-fn detect_language(file_path: &str) -> String {
-    match file_path.rsplit('.').next() {
-        Some("c") => "C",
-        Some("h") => "C Header",
-        Some("rs") => "Rust",
-        Some("py") => "Python",
-        _ => "Unknown",
+fn match_combined_code_kind(line: &str, parents: usize) -> CodeKind {
+    let mut columns = line.bytes();
+    CodeKind::Combined(
+        (0..parents)
+            .map(|_| match columns.next() {
+                Some(b'+') => CodeKind::Add,
+                Some(b'-') => CodeKind::Remove,
+                _ => CodeKind::Context,
+            })
+            .collect(),
+    )
+}
+
+/// Classify an extended header line of a diff (see `MetadataKind`)
+fn match_metadata_kind(line: &str) -> MetadataKind {
+    let line = line.trim_end();
+    let mode = |prefix: &str| line.strip_prefix(prefix).map(|m| m.trim().to_string());
+    let percent = |prefix: &str| {
+        line.strip_prefix(prefix)
+            .and_then(|p| p.trim().trim_end_matches('%').parse().ok())
+    };
+
+    if let Some(index) = line.strip_prefix("index ") {
+        let mut fields = index.split_whitespace();
+        let (old_hash, new_hash) = fields
+            .next()
+            .and_then(|hashes| hashes.split_once(".."))
+            .unwrap_or_default();
+        return MetadataKind::Index {
+            old_hash: old_hash.to_string(),
+            new_hash: new_hash.to_string(),
+            mode: fields.next().map(str::to_string),
+        };
+    }
+    if let Some(mode) = mode("new file mode ") {
+        return MetadataKind::NewFileMode(mode);
+    }
+    if let Some(mode) = mode("deleted file mode ") {
+        return MetadataKind::DeletedFileMode(mode);
+    }
+    if let Some(mode) = mode("old mode ") {
+        return MetadataKind::OldMode(mode);
+    }
+    if let Some(mode) = mode("new mode ") {
+        return MetadataKind::NewMode(mode);
+    }
+    if let Some(similarity) = percent("similarity index ") {
+        return MetadataKind::Similarity(similarity);
     }
-    .to_string()
+    if let Some(dissimilarity) = percent("dissimilarity index ") {
+        return MetadataKind::Dissimilarity(dissimilarity);
+    }
+
+    match line {
+        _ if line.starts_with("rename from ") => MetadataKind::RenameFrom,
+        _ if line.starts_with("rename to ") => MetadataKind::RenameTo,
+        _ if line.starts_with("copy from ") => MetadataKind::CopyFrom,
+        _ if line.starts_with("copy to ") => MetadataKind::CopyTo,
+        _ if line.starts_with("--- ") => MetadataKind::OldFile,
+        _ if line.starts_with("+++ ") => MetadataKind::NewFile,
+        _ if line.starts_with("Binary files ") && line.ends_with(" differ") => MetadataKind::Binary,
+        "GIT binary patch" => MetadataKind::GitBinaryPatch,
+        _ => MetadataKind::Unknown,
+    }
+}
+
+/// Classify a line of a `GIT binary patch` block, `None` if it is not part of one
+///
+/// An empty line is only part of the block if another `literal` or `delta`
+/// block follows (the reverse patch), otherwise it ends the binary patch.
+fn match_binary_kind(line: &str, next: Option<&str>) -> Option<BinaryKind> {
+    let line = line.trim_end();
+
+    if line.is_empty() {
+        let next = next?.trim();
+        return matches!(
+            match_binary_kind(next, None),
+            Some(BinaryKind::Literal(_) | BinaryKind::Delta(_))
+        )
+        .then_some(BinaryKind::Data);
+    }
+    if let Some(size) = line.strip_prefix("literal ") {
+        return size.parse().ok().map(BinaryKind::Literal);
+    }
+    if let Some(size) = line.strip_prefix("delta ") {
+        return size.parse().ok().map(BinaryKind::Delta);
+    }
+
+    /* A length character (`A`-`Z` for 1-26 bytes, `a`-`z` for 27-52) and groups of 5 base85 digits */
+    if !line.as_bytes()[0].is_ascii_alphabetic() {
+        return None;
+    }
+    let data = &line[1..];
+    let base85 = |c: char| c.is_ascii_alphanumeric() || "!#$%&()*+-;<=>?@^_`{|}~".contains(c);
+    (!data.is_empty() && data.len().is_multiple_of(5) && data.chars().all(base85))
+        .then_some(BinaryKind::Data)
 }
 
-/// Get diff-ed code kind: Add (`+`), Remove (`-`), or Context (no sign)
+/// Get diff-ed code kind: Add (`+`), Remove (`-`), or Context (` `)
+///
+/// The marker is the first character of `line`, which starts right after the
+/// quote prefix. Anything else is damage done by a mail client: an empty line
+/// (unless it fits the hunk counts, see `expects_context_line`) or one
+/// starting with a tab is a context line that lost its marker, and a line
+/// starting with any other character is the continuation of a wrapped line
+/// (see `continues_wrapped_line`), which takes the kind of the line before it
+/// (`previous`).
 #[inline]
-fn match_code_kind(line: &str) -> Option<CodeKind> {
-    let first = line.chars().find(|&c| c != ' ' && c != '\t');
-    match first {
-        Some('+') => Some(CodeKind::Add),
-        Some('-') => Some(CodeKind::Remove),
-        Some(_) => Some(CodeKind::Context),
-        None => Some(CodeKind::Context),
+fn match_code_kind(line: &str, previous: Option<CodeKind>) -> (CodeKind, Option<Damage>) {
+    match line.chars().next() {
+        Some('+') => (CodeKind::Add, None),
+        Some('-') => (CodeKind::Remove, None),
+        Some(' ') => (CodeKind::Context, None),
+        Some('\u{a0}') => (CodeKind::Context, Some(Damage::NonBreakingSpace)),
+        None | Some('\t') => (CodeKind::Context, Some(Damage::MissingMarker)),
+        Some(_) => (previous.unwrap_or(CodeKind::Context), Some(Damage::Wrapped)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `source` and get the variant of every line
+    fn variants(source: &str) -> Vec<&'static str> {
+        Line::parse_lines(source, QuoteProfile::default())
+            .iter()
+            .map(|line| match line {
+                Line::Header { .. } => "header",
+                Line::Text { .. } => "text",
+                Line::Separator { .. } => "separator",
+                Line::DiffStat { .. } => "diffstat",
+                Line::Signature { .. } => "signature",
+                Line::DiffHeader { .. } => "diff",
+                Line::DiffMetadata { .. } => "metadata",
+                Line::HunkHeader { .. } => "hunk",
+                Line::BinaryPatch { .. } => "binary",
+                Line::Code { .. } => "code",
+            })
+            .collect()
+    }
+
+    fn range(start: usize, count: usize) -> HunkRange {
+        HunkRange { start, count }
+    }
+
+    #[test]
+    fn hunk_header_with_function_context() {
+        let (old, new, context) =
+            parse_hunk_header("@@ -10,7 +10,8 @@ static int foo(struct bar *b)").unwrap();
+        assert_eq!(old, vec![range(10, 7)]);
+        assert_eq!(new, range(10, 8));
+        assert_eq!(context, "static int foo(struct bar *b)");
+    }
+
+    #[test]
+    fn hunk_header_counts_default_to_one() {
+        let (old, new, context) = parse_hunk_header("@@ -3 +3 @@").unwrap();
+        assert_eq!(old, vec![range(3, 1)]);
+        assert_eq!(new, range(3, 1));
+        assert_eq!(context, "");
+    }
+
+    #[test]
+    fn hunk_header_of_combined_diff() {
+        let (old, new, _) = parse_hunk_header("@@@ -1,3 -1,4 +1,5 @@@").unwrap();
+        assert_eq!(old, vec![range(1, 3), range(1, 4)]);
+        assert_eq!(new, range(1, 5));
+    }
+
+    #[test]
+    fn hunk_header_of_created_file() {
+        let (old, new, _) = parse_hunk_header("@@ -0,0 +1,2 @@").unwrap();
+        assert_eq!(old, vec![range(0, 0)]);
+        assert_eq!(new, range(1, 2));
+    }
+
+    #[test]
+    fn malformed_hunk_headers() {
+        assert!(parse_hunk_header("@ -1 +1 @").is_none());
+        assert!(parse_hunk_header("@@ -1 +1").is_none());
+        assert!(parse_hunk_header("@@ +1,2 -1,2 @@").is_none());
+        assert!(parse_hunk_header("@@ -a,2 +1,2 @@").is_none());
+        /* A combined header needs one old range per parent */
+        assert!(parse_hunk_header("@@@ -1,3 +1,5 @@@").is_none());
+        /* The closing marks must match the opening ones */
+        assert!(parse_hunk_header("@@@ -1,3 -1,4 +1,5 @@").is_none());
+    }
+
+    #[test]
+    fn signature_after_diff_without_hunks() {
+        let source = "diff --git a/a.c b/b.c\n\
+                      similarity index 100%\n\
+                      rename from a.c\n\
+                      rename to b.c\n\
+                      -- \n\
+                      2.40.0\n";
+        assert_eq!(
+            variants(source),
+            [
+                "diff",
+                "metadata",
+                "metadata",
+                "metadata",
+                "signature",
+                "signature"
+            ]
+        );
+    }
+
+    #[test]
+    fn diff_command_in_prose_is_text() {
+        let source = "diff -u between v1 and v2 looks fine to me.\n\
+                      \n\
+                      Reviewed-by: A <a@b.c>\n\
+                      -- \n\
+                      A\n";
+        assert_eq!(
+            variants(source),
+            ["text", "text", "text", "signature", "signature"]
+        );
+    }
+
+    #[test]
+    fn diff_command_followed_by_file_headers() {
+        let source = "diff -up a/f.c b/f.c\n\
+                      --- a/f.c\t2024-01-01\n\
+                      +++ b/f.c\t2024-01-02\n\
+                      @@ -1 +1 @@\n\
+                      -a\n\
+                      +b\n";
+        let lines = Line::parse_lines(source, QuoteProfile::default());
+        let Line::DiffHeader {
+            old_path, new_path, ..
+        } = &lines[0]
+        else {
+            panic!("not a diff header: {:?}", lines[0]);
+        };
+        assert_eq!((old_path.as_str(), new_path.as_str()), ("f.c", "f.c"));
+        assert_eq!(
+            variants(source),
+            ["diff", "metadata", "metadata", "hunk", "code", "code"]
+        );
+    }
+
+    #[test]
+    fn svn_index_line_with_rule() {
+        let source = "Index: fs/foo.c\n\
+                      ==========\n\
+                      --- fs/foo.c\t(revision 1)\n\
+                      +++ fs/foo.c\t(working copy)\n\
+                      @@ -1 +1 @@\n\
+                      -a\n\
+                      +b\n\
+                      Index: is how svn names it.\n";
+        assert_eq!(
+            variants(source),
+            [
+                "diff", "metadata", "metadata", "metadata", "hunk", "code", "code", "text"
+            ]
+        );
+    }
+
+    #[test]
+    fn metadata_ends_at_the_first_other_line() {
+        let source = "diff --git a/x.bin b/x.bin\n\
+                      new file mode 100644\n\
+                      Binary files /dev/null and b/x.bin differ\n\
+                      \n\
+                      bye\n";
+        assert_eq!(
+            variants(source),
+            ["diff", "metadata", "metadata", "text", "text"]
+        );
+    }
+
+    #[test]
+    fn combined_diff_metadata() {
+        let source = "diff --cc f.c\n\
+                      index 1234567,89abcde..fedcba9\n\
+                      mode 100644,100644..100755\n\
+                      --- a/f.c\n\
+                      +++ b/f.c\n\
+                      @@@ -1,1 -1,1 +1,1 @@@\n\
+                      - a\n\
+                       -b\n\
+                      ++c\n";
+        assert_eq!(
+            variants(source),
+            [
+                "diff", "metadata", "metadata", "metadata", "metadata", "hunk", "code", "code",
+                "code"
+            ]
+        );
+    }
+
+    #[test]
+    fn binary_patch_ends_at_non_ascii_line() {
+        let source = "diff --git a/f b/f\n\
+                      GIT binary patch\n\
+                      literal 12\n\
+                      été\n";
+        assert_eq!(variants(source), ["diff", "metadata", "binary", "text"]);
+    }
+
+    fn damages(source: &str) -> Vec<Option<Damage>> {
+        Line::parse_lines(source, QuoteProfile::default())
+            .iter()
+            .filter_map(|line| match line {
+                Line::HunkHeader { damage, .. } | Line::Code { damage, .. } => Some(*damage),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quoted_blank_context_line() {
+        let source = "> diff --git a/f.c b/f.c\n\
+                      > @@ -1,3 +1,3 @@\n\
+                      >  int a;\n\
+                      >\n\
+                      > -int b;\n\
+                      > +int c;\n";
+        assert_eq!(
+            variants(source),
+            ["diff", "hunk", "code", "code", "code", "code"]
+        );
+        assert_eq!(damages(source), [None; 5]);
+    }
+
+    #[test]
+    fn blank_line_beyond_the_counts_is_damage() {
+        let source = "diff --git a/f.c b/f.c\n\
+                      @@ -1,1 +1,2 @@\n\
+                      -int a;\n\
+                      \n\
+                      +int b;\n";
+        let missing = Some(Damage::MissingMarker);
+        assert_eq!(damages(source), [missing, None, missing, None]);
+    }
+
+    #[test]
+    fn reply_after_a_trimmed_quoted_hunk() {
+        let source = "> diff --git a/f.c b/f.c\n\
+                      > @@ -1,10 +1,10 @@\n\
+                      >  int a;\n\
+                      > -int b;\n\
+                      > +int c;\n\
+                      > Why is this needed?\n\
+                      \n\
+                      Because.\n";
+        assert_eq!(
+            variants(source),
+            [
+                "diff", "hunk", "code", "code", "code", "text", "text", "text"
+            ]
+        );
+        assert_eq!(damages(source), [Some(Damage::Truncated), None, None, None]);
+    }
+
+    #[test]
+    fn wrapped_line_continues_the_hunk() {
+        let source = "diff --git a/f.c b/f.c\n\
+                      @@ -1,2 +1,2 @@\n\
+                      -\tret = some_long_function_name(first_argument, second_argument,\n\
+                      third_argument);\n\
+                      +\tret = 0;\n\
+                      \x20int a;\n";
+        let wrapped = Some(Damage::Wrapped);
+        assert_eq!(damages(source), [wrapped, None, wrapped, None, None]);
     }
 }
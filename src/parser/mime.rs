@@ -0,0 +1,429 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::parser::flowed;
+use crate::parser::header::Headers;
+
+/// Media types of the parts that are highlighted, everything else is skipped
+const TEXT_TYPES: &[&str] = &["text/plain", "text/x-patch", "text/x-diff"];
+
+/// A decoded MIME part of a message
+#[derive(Debug, Clone)]
+pub struct MimePart {
+    /// Lowercase media type without parameters (e.g. `text/x-patch`)
+    pub content_type: String,
+    /// File name of an attachment, if any
+    pub filename: Option<String>,
+    /// The body decoded to UTF-8
+    pub text: String,
+}
+
+/// Walk the MIME structure of a message and decode its textual parts
+///
+/// `multipart/*` bodies are split recursively, and the `text/plain`,
+/// `text/x-patch`, and `text/x-diff` parts are decoded from quoted-printable
+/// or base64, converted from their charset to UTF-8, and un-wrapped if they
+/// are `format=flowed` (see `flowed::unflow`).
+///
+/// `headers` are the headers of `message`, parsed from its UTF-8 conversion.
+///
+/// Returns `None` if the message is neither multipart, transfer-encoded,
+/// flowed, nor in a charset other than UTF-8, in which case it can be
+/// parsed as it is.
+pub fn decode_parts(message: &[u8], headers: &Headers) -> Option<Vec<MimePart>> {
+    let content_type = headers.get("Content-Type").unwrap_or("text/plain");
+    let encoding = headers.get("Content-Transfer-Encoding").unwrap_or("7bit");
+
+    if !media_type(content_type).starts_with("multipart/")
+        && !is_transfer_encoded(encoding)
+        && !is_flowed(content_type)
+        && charset_encoding(content_type).is_none()
+    {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    let part = PartInfo {
+        content_type,
+        encoding,
+        disposition: headers.get("Content-Disposition"),
+    };
+    walk(
+        message,
+        body_start(message, headers),
+        message.len(),
+        &part,
+        &mut parts,
+    );
+    Some(parts)
+}
+
+/// Check if the message is a `multipart/*` message
+pub fn is_multipart(headers: &Headers) -> bool {
+    headers
+        .get("Content-Type")
+        .is_some_and(|ct| media_type(ct).starts_with("multipart/"))
+}
+
+/// Get the offset of the body, skipping the empty line after the header block
+///
+/// `headers` are parsed from the UTF-8 conversion of `message`, so their
+/// offsets may not match. Their block ends with the first empty line,
+/// which is looked up in `message` itself instead.
+pub fn body_start(message: &[u8], headers: &Headers) -> usize {
+    let mut headers_end = 0;
+    if headers.end > 0 {
+        for line in message.split_inclusive(|&b| b == b'\n') {
+            if line.iter().all(|&b| b == b'\n' || b == b'\r') {
+                break;
+            }
+            headers_end += line.len();
+        }
+    }
+
+    let rest = &message[headers_end..];
+    if rest.starts_with(b"\r\n") {
+        headers_end + 2
+    } else if rest.starts_with(b"\n") {
+        headers_end + 1
+    } else {
+        headers_end
+    }
+}
+
+/// The MIME headers relevant for decoding a part
+struct PartInfo<'a> {
+    content_type: &'a str,
+    encoding: &'a str,
+    disposition: Option<&'a str>,
+}
+
+/// Decode the part in `message[start..end]`, recursing into multipart bodies
+fn walk(message: &[u8], start: usize, end: usize, info: &PartInfo, parts: &mut Vec<MimePart>) {
+    let mtype = media_type(info.content_type);
+
+    if mtype.starts_with("multipart/") {
+        let Some(boundary) = parameter(info.content_type, "boundary") else {
+            return;
+        };
+        for (part_start, part_end) in split_multipart(message, start, end, &boundary) {
+            let part = &message[part_start..part_end];
+            let source = String::from_utf8_lossy(part);
+            let headers = Headers::parse(&source);
+            let info = PartInfo {
+                content_type: headers.get("Content-Type").unwrap_or("text/plain"),
+                encoding: headers.get("Content-Transfer-Encoding").unwrap_or("7bit"),
+                disposition: headers.get("Content-Disposition"),
+            };
+            let body = part_start + body_start(part, &headers);
+            walk(message, body, part_end, &info, parts);
+        }
+    } else if TEXT_TYPES.contains(&mtype.as_str()) {
+        let mut text = decode_body(&message[start..end], info.encoding, info.content_type);
+        if is_flowed(info.content_type) {
+            let delsp = parameter(info.content_type, "delsp")
+                .is_some_and(|d| d.eq_ignore_ascii_case("yes"));
+            text = flowed::unflow(&text, delsp);
+        }
+        let filename = info
+            .disposition
+            .and_then(|d| parameter(d, "filename"))
+            .or_else(|| parameter(info.content_type, "name"));
+
+        parts.push(MimePart {
+            content_type: mtype,
+            filename,
+            text,
+        });
+    }
+}
+
+/// Split a multipart body into the byte ranges of its parts
+///
+/// The line break in front of a delimiter line belongs to the delimiter.
+/// The preamble and the epilogue are dropped.
+fn split_multipart(
+    message: &[u8],
+    start: usize,
+    end: usize,
+    boundary: &str,
+) -> Vec<(usize, usize)> {
+    let delimiter = format!("--{boundary}");
+    let mut ranges = Vec::new();
+    let mut part_start = None;
+    let mut offset = start;
+
+    for chunk in message[start..end].split_inclusive(|&b| b == b'\n') {
+        let line = chunk.trim_ascii_end();
+        if let Some(rest) = line.strip_prefix(delimiter.as_bytes())
+            && (rest.is_empty() || rest == b"--")
+        {
+            if let Some(part_start) = part_start {
+                let part_end = message[..offset]
+                    .strip_suffix(b"\n")
+                    .map(|m| m.strip_suffix(b"\r").unwrap_or(m).len())
+                    .unwrap_or(offset)
+                    .max(part_start);
+                ranges.push((part_start, part_end));
+            }
+            if rest == b"--" {
+                return ranges;
+            }
+            part_start = Some(offset + chunk.len());
+        }
+        offset += chunk.len();
+    }
+
+    /* Missing closing delimiter, take everything up to the end */
+    if let Some(part_start) = part_start {
+        ranges.push((part_start, end));
+    }
+    ranges
+}
+
+/// Decode a body according to its transfer encoding and the charset of its `Content-Type`
+fn decode_body(body: &[u8], encoding: &str, content_type: &str) -> String {
+    let bytes = match encoding.trim().to_ascii_lowercase().as_str() {
+        "quoted-printable" => body
+            .split_inclusive(|&b| b == b'\n')
+            .flat_map(decode_quoted_printable_line)
+            .collect(),
+        "base64" => {
+            let compact: Vec<u8> = body
+                .iter()
+                .copied()
+                .filter(|b| !b.is_ascii_whitespace())
+                .collect();
+            /* A body that is not valid base64 is shown as it is */
+            STANDARD.decode(compact).unwrap_or_else(|_| body.to_vec())
+        }
+        _ => body.to_vec(),
+    };
+
+    match charset_encoding(content_type) {
+        Some(charset) => charset.decode_without_bom_handling(&bytes).0.into_owned(),
+        None => String::from_utf8_lossy(&bytes).into_owned(),
+    }
+}
+
+/// Get the encoding of the `charset` of a `Content-Type`, `None` if it is UTF-8 or unknown
+///
+/// `us-ascii` counts as UTF-8 too. Mail clients use it as the default,
+/// including for bodies that turn out to be UTF-8, which is a superset of it.
+fn charset_encoding(content_type: &str) -> Option<&'static Encoding> {
+    let charset = parameter(content_type, "charset")?;
+    if charset.eq_ignore_ascii_case("us-ascii") {
+        return None;
+    }
+    Encoding::for_label(charset.as_bytes()).filter(|&encoding| encoding != UTF_8)
+}
+
+/// Decode one line of a quoted-printable body
+///
+/// A soft line break (`=` at the end of the line) is dropped, so the line
+/// continues with the next one. Hard line breaks are decoded to `\n`.
+/// Malformed escapes are kept as they are.
+fn decode_quoted_printable_line(line: &[u8]) -> Vec<u8> {
+    let has_break = line.ends_with(b"\n");
+    let mut content = line;
+    while let [rest @ .., b'\n' | b'\r'] = content {
+        content = rest;
+    }
+    /* Transport padding is not part of the content */
+    while let [rest @ .., b' ' | b'\t'] = content {
+        content = rest;
+    }
+
+    let (content, soft_break) = match content.strip_suffix(b"=") {
+        Some(content) => (content, true),
+        None => (content, false),
+    };
+
+    let mut decoded = Vec::with_capacity(content.len() + 1);
+    let mut i = 0;
+    while i < content.len() {
+        if content[i] == b'='
+            && i + 2 < content.len()
+            && let (Some(hi), Some(lo)) = (hex_value(content[i + 1]), hex_value(content[i + 2]))
+        {
+            decoded.push(hi << 4 | lo);
+            i += 3;
+        } else {
+            decoded.push(content[i]);
+            i += 1;
+        }
+    }
+
+    if has_break && !soft_break {
+        decoded.push(b'\n');
+    }
+    decoded
+}
+
+#[inline]
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|v| v as u8)
+}
+
+/// Check if a `Content-Transfer-Encoding` needs decoding
+#[inline]
+fn is_transfer_encoded(encoding: &str) -> bool {
+    let encoding = encoding.trim();
+    encoding.eq_ignore_ascii_case("quoted-printable") || encoding.eq_ignore_ascii_case("base64")
+}
+
+/// Check if a `Content-Type` is `format=flowed` (RFC 3676)
+#[inline]
+fn is_flowed(content_type: &str) -> bool {
+    parameter(content_type, "format").is_some_and(|f| f.eq_ignore_ascii_case("flowed"))
+}
+
+/// Get the lowercase media type of a `Content-Type` value
+#[inline]
+fn media_type(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Get a parameter of a structured header value (e.g. `charset` of `Content-Type`)
+fn parameter(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, val) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| val.trim().trim_matches('"').to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(message: &[u8]) -> Option<Vec<MimePart>> {
+        let source = String::from_utf8_lossy(message);
+        decode_parts(message, &Headers::parse(&source))
+    }
+
+    #[test]
+    fn plain_utf8_message_is_left_alone() {
+        assert!(decode(b"Subject: x\n\ncaf\xc3\xa9\n").is_none());
+        /* `us-ascii` is a subset of UTF-8, and often a wrong label for it */
+        assert!(decode(b"Content-Type: text/plain; charset=us-ascii\n\ncaf\xc3\xa9\n").is_none());
+    }
+
+    #[test]
+    fn eight_bit_body_in_legacy_charset() {
+        let parts = decode(
+            b"Content-Type: text/plain; charset=iso-8859-1\n\
+              Content-Transfer-Encoding: 8bit\n\
+              \n\
+              caf\xe9\n",
+        )
+        .unwrap();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].text, "caf\u{e9}\n");
+    }
+
+    #[test]
+    fn quoted_printable_body_in_legacy_charset() {
+        let parts = decode(
+            b"Content-Type: text/plain; charset=koi8-r\n\
+              Content-Transfer-Encoding: quoted-printable\n\
+              \n\
+              =D0=D2=C9=D7=C5=D4\n",
+        )
+        .unwrap();
+        assert_eq!(
+            parts[0].text,
+            "\u{43f}\u{440}\u{438}\u{432}\u{435}\u{442}\n"
+        );
+    }
+
+    #[test]
+    fn multipart_keeps_text_parts_only() {
+        let message = b"Content-Type: multipart/mixed; boundary=\"xyz\"\n\
+                        \n\
+                        preamble\n\
+                        --xyz\n\
+                        Content-Type: text/plain\n\
+                        \n\
+                        hello\n\
+                        --xyz\n\
+                        Content-Type: image/png\n\
+                        Content-Transfer-Encoding: base64\n\
+                        \n\
+                        iVBORw0KGgo=\n\
+                        --xyz\n\
+                        Content-Type: text/x-patch; name=\"fix.patch\"\n\
+                        Content-Transfer-Encoding: base64\n\
+                        \n\
+                        ZGlmZiAtLWdpdAo=\n\
+                        --xyz--\n\
+                        epilogue\n";
+        let parts = decode(message).unwrap();
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].content_type, "text/plain");
+        assert_eq!(parts[0].text, "hello");
+        assert_eq!(parts[1].content_type, "text/x-patch");
+        assert_eq!(parts[1].filename.as_deref(), Some("fix.patch"));
+        assert_eq!(parts[1].text, "diff --git\n");
+    }
+
+    #[test]
+    fn body_start_skips_the_empty_line() {
+        let message = b"Subject: caf\xe9\r\n\r\nbody\n";
+        let source = String::from_utf8_lossy(message);
+        let headers = Headers::parse(&source);
+        assert_eq!(body_start(message, &headers), 17);
+        /* Without headers, the body starts right away */
+        assert_eq!(body_start(b"body\n", &Headers::default()), 0);
+    }
+
+    #[test]
+    fn quoted_printable_escapes() {
+        assert_eq!(decode_quoted_printable_line(b"a=3Db\n"), b"a=b\n");
+        assert_eq!(
+            decode_quoted_printable_line(b"caf=C3=A9\r\n"),
+            b"caf\xc3\xa9\n"
+        );
+        /* Lowercase hex digits are accepted, malformed escapes kept */
+        assert_eq!(
+            decode_quoted_printable_line(b"=c3=a9 =ZZ =4\n"),
+            b"\xc3\xa9 =ZZ =4\n"
+        );
+    }
+
+    #[test]
+    fn quoted_printable_soft_breaks_and_padding() {
+        assert_eq!(decode_quoted_printable_line(b"long li=\n"), b"long li");
+        assert_eq!(decode_quoted_printable_line(b"long li=  \r\n"), b"long li");
+        assert_eq!(decode_quoted_printable_line(b"padded \t\n"), b"padded\n");
+        /* An encoded trailing space survives */
+        assert_eq!(decode_quoted_printable_line(b"space=20\n"), b"space \n");
+        /* The last line of a body may have no line break */
+        assert_eq!(decode_quoted_printable_line(b"end"), b"end");
+    }
+
+    #[test]
+    fn quoted_printable_body() {
+        let body = b"int a =3D 1;=\n2;\nnext\n";
+        assert_eq!(
+            decode_body(body, "quoted-printable", "text/plain"),
+            "int a = 1;2;\nnext\n"
+        );
+    }
+
+    #[test]
+    fn invalid_base64_is_kept() {
+        assert_eq!(
+            decode_body(b"not base64!\n", "base64", "text/plain"),
+            "not base64!\n"
+        );
+    }
+}
@@ -1,2 +1,7 @@
+pub mod flowed;
+pub mod header;
 pub mod line;
+pub mod mime;
+pub mod path;
+pub mod quote;
 pub mod span;
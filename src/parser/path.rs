@@ -0,0 +1,324 @@
+/// Which side of a diff a path belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathSide {
+    Old,
+    New,
+}
+
+/// The path git uses for the missing side of a created or deleted file
+pub const DEV_NULL: &str = "/dev/null";
+
+/// Get the old and new path of a `diff --git`, `diff --cc`, or `diff --combined` line
+///
+/// Handles C-quoted paths (`"a/foo\tbar"`), paths with spaces, and the
+/// `--no-prefix` and `--src-prefix`/`--dst-prefix` variants. Without
+/// quotes, the split between both paths is ambiguous if they contain
+/// spaces, so the split where both paths name the same file wins. Renames
+/// and copies are only split on a best effort basis, their exact paths
+/// follow in the `rename from`/`rename to` metadata lines.
+pub fn git_diff_paths(line: &str) -> Option<(String, String)> {
+    if let Some(path) = line
+        .strip_prefix("diff --cc ")
+        .or_else(|| line.strip_prefix("diff --combined "))
+    {
+        /* Combined diffs name the merge result only */
+        let path = parse_path(path.trim()).0;
+        return Some((path.clone(), path));
+    }
+
+    let args = line.strip_prefix("diff --git ")?.trim_end();
+
+    if args.starts_with('"') || args.ends_with('"') {
+        let (old, rest) = match unquote(args) {
+            Some((old, rest)) => (old, rest.trim_start()),
+            None => {
+                let (old, _) = args.rsplit_once(" \"")?;
+                (old.to_string(), &args[old.len() + 1..])
+            }
+        };
+        let new = parse_path(rest).0;
+        return Some(strip_default_prefixes(old, new));
+    }
+
+    /* The same file on both sides, with or without a prefix each */
+    let spaces = args.match_indices(' ').map(|(i, _)| i);
+    for i in spaces.clone() {
+        if args[..i] == args[i + 1..] {
+            return Some((args[..i].to_string(), args[..i].to_string()));
+        }
+    }
+    for i in spaces.clone() {
+        if let (Some((_, old)), Some((_, new))) =
+            (args[..i].split_once('/'), args[i + 1..].split_once('/'))
+            && !old.is_empty()
+            && old == new
+        {
+            return Some((old.to_string(), new.to_string()));
+        }
+    }
+
+    /* A rename or a copy */
+    let split = args
+        .find(" b/")
+        .or_else(|| spaces.clone().next())
+        .unwrap_or(args.len());
+    let new = args.get(split + 1..).unwrap_or_default();
+    Some(strip_default_prefixes(
+        args[..split].to_string(),
+        new.to_string(),
+    ))
+}
+
+/// Get the old and new path of a `diff -up old new` command line
+///
+/// These are the last two arguments of the command line.
+pub fn diff_command_paths(line: &str) -> Option<(String, String)> {
+    let mut args = line
+        .split_whitespace()
+        .skip(1)
+        .filter(|a| !a.starts_with('-'));
+    let old = args.next()?;
+    let new = args.last().unwrap_or(old);
+    Some(strip_default_prefixes(old.to_string(), new.to_string()))
+}
+
+/// Get the path of a `---` or `+++` file header without its marker
+///
+/// C-quoted paths are unquoted, the timestamp that `diff -u` separates
+/// with a tab is dropped, and so is the `a/` or `b/` prefix.
+/// `/dev/null` is returned as it is (see `DEV_NULL`).
+pub fn file_header_path(header: &str) -> String {
+    let (path, _) = parse_path(header.trim_start());
+    path.strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .map(str::to_string)
+        .unwrap_or(path)
+}
+
+/// Get the path named by a line of the extended git diff metadata
+///
+/// These are `rename from`/`rename to`, `copy from`/`copy to`, and the
+/// `---`/`+++` file headers. Returns `None` for any other line.
+pub fn metadata_path(line: &str) -> Option<(PathSide, String)> {
+    if let Some(header) = line.strip_prefix("--- ") {
+        return Some((PathSide::Old, file_header_path(header)));
+    }
+    if let Some(header) = line.strip_prefix("+++ ") {
+        return Some((PathSide::New, file_header_path(header)));
+    }
+
+    let (side, path) = if let Some(path) = line
+        .strip_prefix("rename from ")
+        .or_else(|| line.strip_prefix("copy from "))
+    {
+        (PathSide::Old, path)
+    } else {
+        let path = line
+            .strip_prefix("rename to ")
+            .or_else(|| line.strip_prefix("copy to "))?;
+        (PathSide::New, path)
+    };
+
+    /* No prefixes here, and no trailing timestamp either */
+    let path = match unquote(path) {
+        Some((path, _)) => path,
+        None => path.trim_end().to_string(),
+    };
+    Some((side, path))
+}
+
+/// Parse a path that may be C-quoted, up to a tab or the end of the line
+///
+/// Returns the path and the rest of the line after it.
+fn parse_path(s: &str) -> (String, &str) {
+    if let Some(unquoted) = unquote(s) {
+        return unquoted;
+    }
+    match s.split_once('\t') {
+        Some((path, rest)) => (path.trim_end().to_string(), rest),
+        None => (s.trim_end().to_string(), ""),
+    }
+}
+
+/// Unquote a path that git quoted C-style, because it contains special characters
+///
+/// Handles the `\a`, `\b`, `\t`, `\n`, `\v`, `\f`, `\r`, `\"`, and `\\`
+/// escapes, and octal escapes (`\303\251`) of the bytes of non-ASCII
+/// characters. Returns the path and the rest of `s` after the closing
+/// quote, or `None` if `s` does not start with a well-formed quoted path.
+pub fn unquote(s: &str) -> Option<(String, &str)> {
+    let bytes = s.as_bytes();
+    if bytes.first() != Some(&b'"') {
+        return None;
+    }
+
+    let mut path = Vec::new();
+    let mut i = 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                let path = String::from_utf8_lossy(&path).into_owned();
+                return Some((path, &s[i + 1..]));
+            }
+            b'\\' => {
+                let escaped = *bytes.get(i + 1)?;
+                let byte = match escaped {
+                    b'a' => 0x07,
+                    b'b' => 0x08,
+                    b't' => b'\t',
+                    b'n' => b'\n',
+                    b'v' => 0x0b,
+                    b'f' => 0x0c,
+                    b'r' => b'\r',
+                    b'0'..=b'3' => {
+                        let octal = s.get(i + 1..i + 4)?;
+                        let byte = u8::from_str_radix(octal, 8).ok()?;
+                        i += 2;
+                        byte
+                    }
+                    other => other,
+                };
+                path.push(byte);
+                i += 2;
+            }
+            byte => {
+                path.push(byte);
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+/// Strip git's default `a/` and `b/` prefixes, if both paths have them
+fn strip_default_prefixes(old: String, new: String) -> (String, String) {
+    match (old.strip_prefix("a/"), new.strip_prefix("b/")) {
+        (Some(old), Some(new)) => (old.to_string(), new.to_string()),
+        _ => (old, new),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(old: &str, new: &str) -> Option<(String, String)> {
+        Some((old.to_string(), new.to_string()))
+    }
+
+    #[test]
+    fn unquote_escapes() {
+        assert_eq!(
+            unquote(r#""a\tb\\c\"d" rest"#),
+            Some(("a\tb\\c\"d".to_string(), " rest"))
+        );
+        assert_eq!(
+            unquote(r#""\a\b\n\v\f\r""#).unwrap().0,
+            "\x07\x08\n\x0b\x0c\r"
+        );
+    }
+
+    #[test]
+    fn unquote_octal_escapes() {
+        assert_eq!(unquote(r#""caf\303\251.txt""#).unwrap().0, "café.txt");
+        assert_eq!(unquote(r#""\101\0611""#).unwrap().0, "A11");
+    }
+
+    #[test]
+    fn unquote_malformed() {
+        assert_eq!(unquote("plain"), None);
+        assert_eq!(unquote(r#""unterminated"#), None);
+        assert_eq!(unquote(r#""trailing\"#), None);
+        assert_eq!(unquote(r#""short\30"#), None);
+        assert_eq!(unquote(r#""bad\389""#), None);
+    }
+
+    #[test]
+    fn git_diff_paths_plain() {
+        assert_eq!(
+            git_diff_paths("diff --git a/f.c b/f.c"),
+            paths("f.c", "f.c")
+        );
+        assert_eq!(
+            git_diff_paths("diff --git a/my file.c b/my file.c"),
+            paths("my file.c", "my file.c")
+        );
+        assert_eq!(git_diff_paths("diff --git f.c f.c"), paths("f.c", "f.c"));
+        assert_eq!(
+            git_diff_paths("diff --git x/dir/f.c y/dir/f.c"),
+            paths("dir/f.c", "dir/f.c")
+        );
+    }
+
+    #[test]
+    fn git_diff_paths_quoted() {
+        assert_eq!(
+            git_diff_paths(r#"diff --git "a/caf\303\251" "b/caf\303\251""#),
+            paths("café", "café")
+        );
+        assert_eq!(
+            git_diff_paths(r#"diff --git a/plain "b/tab\there""#),
+            paths("plain", "tab\there")
+        );
+        assert_eq!(
+            git_diff_paths(r#"diff --git "a/tab\there" b/plain"#),
+            paths("tab\there", "plain")
+        );
+    }
+
+    #[test]
+    fn git_diff_paths_rename() {
+        assert_eq!(
+            git_diff_paths("diff --git a/old.c b/new.c"),
+            paths("old.c", "new.c")
+        );
+        assert_eq!(
+            git_diff_paths("diff --git a/old name.c b/new name.c"),
+            paths("old name.c", "new name.c")
+        );
+    }
+
+    #[test]
+    fn git_diff_paths_combined() {
+        assert_eq!(git_diff_paths("diff --cc f.c"), paths("f.c", "f.c"));
+        assert_eq!(git_diff_paths("diff --combined f.c"), paths("f.c", "f.c"));
+        assert_eq!(git_diff_paths("diff -u a b"), None);
+    }
+
+    #[test]
+    fn diff_command_paths_skip_options() {
+        assert_eq!(
+            diff_command_paths("diff -up a/f.c b/f.c"),
+            paths("f.c", "f.c")
+        );
+        assert_eq!(
+            diff_command_paths("diff -u -r orig/f.c new/f.c"),
+            paths("orig/f.c", "new/f.c")
+        );
+    }
+
+    #[test]
+    fn file_header_path_drops_timestamp_and_prefix() {
+        assert_eq!(file_header_path("a/f.c\t2024-01-01 10:00:00"), "f.c");
+        assert_eq!(file_header_path("b/my file.c"), "my file.c");
+        assert_eq!(file_header_path(r#""b/caf\303\251""#), "café");
+        assert_eq!(file_header_path(DEV_NULL), DEV_NULL);
+    }
+
+    #[test]
+    fn metadata_paths() {
+        assert_eq!(
+            metadata_path("rename from a b.c"),
+            Some((PathSide::Old, "a b.c".to_string()))
+        );
+        assert_eq!(
+            metadata_path(r#"copy to "caf\303\251""#),
+            Some((PathSide::New, "café".to_string()))
+        );
+        assert_eq!(
+            metadata_path("+++ b/f.c"),
+            Some((PathSide::New, "f.c".to_string()))
+        );
+        assert_eq!(metadata_path("index 1234567..89abcde"), None);
+    }
+}
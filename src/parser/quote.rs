@@ -0,0 +1,155 @@
+/// A set of rules to recognize the quote prefix of a line
+///
+/// The quote prefix is made of quote marks, one per quoting layer, and a
+/// single space after the last one. Everything else on the line is content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuoteProfile {
+    /// `>` marks at the very beginning of the line, without anything in between (RFC 3676)
+    Strict,
+    /// `>` marks, with optional whitespace in front of and in between them (`> > `, ` >>`)
+    #[default]
+    Lenient,
+    /// `>` and `|` marks, as some mail clients quote with `| `
+    Pipe,
+    /// `>` marks and supercite attributions (`Linus> `, `AM> `)
+    Supercite,
+}
+
+/// Longest name of a supercite attribution (`Name> `)
+const MAX_SUPERCITE_NAME: usize = 20;
+
+impl QuoteProfile {
+    /// The names of the profiles, as accepted by `from_name`
+    pub const NAMES: &[&str] = &["strict", "lenient", "pipe", "supercite"];
+
+    /// Get a profile by its name (see `NAMES`)
+    pub fn from_name(name: &str) -> Option<QuoteProfile> {
+        match name {
+            "strict" => Some(QuoteProfile::Strict),
+            "lenient" => Some(QuoteProfile::Lenient),
+            "pipe" => Some(QuoteProfile::Pipe),
+            "supercite" => Some(QuoteProfile::Supercite),
+            _ => None,
+        }
+    }
+
+    /// Split a line into its quoting layer, its quote prefix, and its content
+    ///
+    /// The prefix is returned as written, so it can be restored exactly.
+    /// Lines that are not quoted have the quoting layer 0 and an empty prefix.
+    pub fn split(self, line: &str) -> (usize, &str, &str) {
+        let mut layers = 0;
+        let mut end = 0;
+
+        loop {
+            let mark_start = match self {
+                QuoteProfile::Strict => end,
+                _ => end + (line[end..].len() - line[end..].trim_start_matches([' ', '\t']).len()),
+            };
+            let Some(len) = self.mark_len(&line[mark_start..]) else {
+                break;
+            };
+            layers += 1;
+            end = mark_start + len;
+        }
+
+        /* The space following the last mark belongs to the prefix */
+        if layers > 0 && line[end..].starts_with(' ') {
+            end += 1;
+        }
+        (layers, &line[..end], &line[end..])
+    }
+
+    /// Get the byte length of the quote mark at the start of `rest`, if there is one
+    fn mark_len(self, rest: &str) -> Option<usize> {
+        if rest.starts_with('>') {
+            return Some(1);
+        }
+        match self {
+            QuoteProfile::Pipe if rest.starts_with('|') => Some(1),
+            QuoteProfile::Supercite => supercite_len(rest),
+            _ => None,
+        }
+    }
+}
+
+/// Get the byte length of a supercite attribution (`Name>`) at the start of `rest`
+///
+/// The name is a short alphanumeric word, and the `>` must be followed by
+/// whitespace or the end of the line, so code like `a>b` is not mistaken
+/// for an attribution.
+fn supercite_len(rest: &str) -> Option<usize> {
+    let name_len = rest
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(rest.len());
+    if name_len == 0 || name_len > MAX_SUPERCITE_NAME {
+        return None;
+    }
+
+    let after = rest[name_len..].strip_prefix('>')?;
+    (after.is_empty() || after.starts_with([' ', '\t'])).then_some(name_len + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unquoted_lines() {
+        for profile in [QuoteProfile::Strict, QuoteProfile::Supercite] {
+            assert_eq!(profile.split("hello > world"), (0, "", "hello > world"));
+            assert_eq!(profile.split(""), (0, "", ""));
+        }
+    }
+
+    #[test]
+    fn strict_marks_start_the_line() {
+        let strict = QuoteProfile::Strict;
+        assert_eq!(strict.split(">> text"), (2, ">> ", "text"));
+        assert_eq!(strict.split(">  indented"), (1, "> ", " indented"));
+        assert_eq!(strict.split("> > text"), (1, "> ", "> text"));
+        assert_eq!(strict.split(" > text"), (0, "", " > text"));
+    }
+
+    #[test]
+    fn lenient_allows_whitespace_around_marks() {
+        let lenient = QuoteProfile::Lenient;
+        assert_eq!(lenient.split("> > text"), (2, "> > ", "text"));
+        assert_eq!(lenient.split(" >>\ttext"), (2, " >>", "\ttext"));
+        assert_eq!(lenient.split(">"), (1, ">", ""));
+        assert_eq!(lenient.split("| text"), (0, "", "| text"));
+    }
+
+    #[test]
+    fn pipe_marks() {
+        let pipe = QuoteProfile::Pipe;
+        assert_eq!(pipe.split("| > text"), (2, "| > ", "text"));
+        assert_eq!(pipe.split("|| a | b"), (2, "|| ", "a | b"));
+    }
+
+    #[test]
+    fn supercite_attributions() {
+        let supercite = QuoteProfile::Supercite;
+        assert_eq!(supercite.split("Linus> text"), (1, "Linus> ", "text"));
+        assert_eq!(supercite.split("AM> > text"), (2, "AM> > ", "text"));
+        assert_eq!(supercite.split("> jo_2>"), (2, "> jo_2>", ""));
+        assert_eq!(supercite.split("if a>b then"), (0, "", "if a>b then"));
+        assert_eq!(supercite.split("a>b"), (0, "", "a>b"));
+    }
+
+    #[test]
+    fn supercite_name_length_limit() {
+        let supercite = QuoteProfile::Supercite;
+        let name = "n".repeat(MAX_SUPERCITE_NAME);
+        assert_eq!(supercite.split(&format!("{name}> x")).0, 1);
+        assert_eq!(supercite.split(&format!("{name}n> x")).0, 0);
+    }
+
+    #[test]
+    fn profile_names() {
+        for name in QuoteProfile::NAMES {
+            assert!(QuoteProfile::from_name(name).is_some());
+        }
+        assert_eq!(QuoteProfile::from_name("outlook"), None);
+    }
+}
@@ -1,40 +1,109 @@
-use crate::parser::line::{CodeKind, Line};
+use crate::parser::line::Line;
 
 #[derive(Debug, Clone)]
 pub enum Span<'a> {
+    Headers {
+        start: usize,
+        end: usize,
+        lines: &'a [Line<'a>],
+    },
     Text {
         start: usize,
         end: usize,
-        quoting_layer: usize,
+        lines: &'a [Line<'a>],
+    },
+    Separator {
+        start: usize,
+        end: usize,
+        lines: &'a [Line<'a>],
+    },
+    DiffStat {
+        start: usize,
+        end: usize,
+        lines: &'a [Line<'a>],
+    },
+    Signature {
+        start: usize,
+        end: usize,
         lines: &'a [Line<'a>],
     },
     DiffHeader {
         start: usize,
         end: usize,
-        quoting_layer: usize,
         lines: &'a [Line<'a>],
     },
     DiffMetadata {
         start: usize,
         end: usize,
-        quoting_layer: usize,
         lines: &'a [Line<'a>],
     },
     HunkHeader {
         start: usize,
         end: usize,
-        quoting_layer: usize,
+        lines: &'a [Line<'a>],
+    },
+    BinaryPatch {
+        start: usize,
+        end: usize,
         lines: &'a [Line<'a>],
     },
     Code {
         start: usize,
         end: usize,
-        quoting_layer: usize,
-        kind: CodeKind,
         lines: &'a [Line<'a>],
     },
 }
 
+impl<'a> Span<'a> {
+    /// Get the start offset of the span
+    pub fn get_start(&self) -> usize {
+        match self {
+            Span::Headers { start, .. }
+            | Span::Text { start, .. }
+            | Span::Separator { start, .. }
+            | Span::DiffStat { start, .. }
+            | Span::Signature { start, .. }
+            | Span::DiffHeader { start, .. }
+            | Span::DiffMetadata { start, .. }
+            | Span::HunkHeader { start, .. }
+            | Span::BinaryPatch { start, .. }
+            | Span::Code { start, .. } => *start,
+        }
+    }
+
+    /// Get the end offset of the span content
+    pub fn get_end(&self) -> usize {
+        match self {
+            Span::Headers { end, .. }
+            | Span::Text { end, .. }
+            | Span::Separator { end, .. }
+            | Span::DiffStat { end, .. }
+            | Span::Signature { end, .. }
+            | Span::DiffHeader { end, .. }
+            | Span::DiffMetadata { end, .. }
+            | Span::HunkHeader { end, .. }
+            | Span::BinaryPatch { end, .. }
+            | Span::Code { end, .. } => *end,
+        }
+    }
+
+    /// Get the lines that make up the span
+    pub fn get_lines(&self) -> &'a [Line<'a>] {
+        match self {
+            Span::Headers { lines, .. }
+            | Span::Text { lines, .. }
+            | Span::Separator { lines, .. }
+            | Span::DiffStat { lines, .. }
+            | Span::Signature { lines, .. }
+            | Span::DiffHeader { lines, .. }
+            | Span::DiffMetadata { lines, .. }
+            | Span::HunkHeader { lines, .. }
+            | Span::BinaryPatch { lines, .. }
+            | Span::Code { lines, .. } => lines,
+        }
+    }
+}
+
 /// Create a vector of `Span`-s from a vector of `Line`-s
 pub fn build_spans<'a>(lines: &'a [Line<'a>]) -> Vec<Span<'a>> {
     let mut spans = Vec::new();
@@ -50,56 +119,54 @@ pub fn build_spans<'a>(lines: &'a [Line<'a>]) -> Vec<Span<'a>> {
             || !lines[start_idx].same_variant(&lines[i])
         {
             let span = match &lines[start_idx] {
-                Line::Text {
-                    offset,
-                    quoting_layer,
-                    ..
-                } => Span::Text {
+                Line::Header { offset, .. } => Span::Headers {
+                    start: *offset,
+                    end: lines[i - 1].get_end_offset(),
+                    lines: &lines[start_idx..i],
+                },
+                Line::Text { offset, .. } => Span::Text {
+                    start: *offset,
+                    end: lines[i - 1].get_end_offset(),
+                    lines: &lines[start_idx..i],
+                },
+                Line::Separator { offset, .. } => Span::Separator {
+                    start: *offset,
+                    end: lines[i - 1].get_end_offset(),
+                    lines: &lines[start_idx..i],
+                },
+                Line::DiffStat { offset, .. } => Span::DiffStat {
+                    start: *offset,
+                    end: lines[i - 1].get_end_offset(),
+                    lines: &lines[start_idx..i],
+                },
+                Line::Signature { offset, .. } => Span::Signature {
+                    start: *offset,
+                    end: lines[i - 1].get_end_offset(),
+                    lines: &lines[start_idx..i],
+                },
+                Line::DiffHeader { offset, .. } => Span::DiffHeader {
                     start: *offset,
                     end: lines[i - 1].get_end_offset(),
-                    quoting_layer: *quoting_layer,
                     lines: &lines[start_idx..i],
                 },
-                Line::DiffHeader {
-                    offset,
-                    quoting_layer,
-                    ..
-                } => Span::DiffHeader {
+                Line::DiffMetadata { offset, .. } => Span::DiffMetadata {
                     start: *offset,
                     end: lines[i - 1].get_end_offset(),
-                    quoting_layer: *quoting_layer,
                     lines: &lines[start_idx..i],
                 },
-                Line::DiffMetadata {
-                    offset,
-                    quoting_layer,
-                    ..
-                } => Span::DiffMetadata {
+                Line::HunkHeader { offset, .. } => Span::HunkHeader {
                     start: *offset,
                     end: lines[i - 1].get_end_offset(),
-                    quoting_layer: *quoting_layer,
                     lines: &lines[start_idx..i],
                 },
-                Line::HunkHeader {
-                    offset,
-                    quoting_layer,
-                    ..
-                } => Span::HunkHeader {
+                Line::BinaryPatch { offset, .. } => Span::BinaryPatch {
                     start: *offset,
                     end: lines[i - 1].get_end_offset(),
-                    quoting_layer: *quoting_layer,
                     lines: &lines[start_idx..i],
                 },
-                Line::Code {
-                    offset,
-                    quoting_layer,
-                    kind,
-                    ..
-                } => Span::Code {
+                Line::Code { offset, .. } => Span::Code {
                     start: *offset,
                     end: lines[i - 1].get_end_offset(),
-                    quoting_layer: *quoting_layer,
-                    kind: kind.clone(),
                     lines: &lines[start_idx..i],
                 },
             };
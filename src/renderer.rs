@@ -0,0 +1,121 @@
+use crate::debug;
+use crate::highlighter::HighlighterEngine;
+use crate::parser::header::Headers;
+use crate::parser::line::Line;
+use crate::parser::mime;
+use crate::parser::quote::QuoteProfile;
+use crate::parser::span::{self, Span};
+
+/// Render every message of an mbox, each preceded by a message separator
+///
+/// Every message is parsed on its own, so no parser state leaks from one
+/// message into the next.
+pub fn render_mbox(
+    engine: &mut HighlighterEngine,
+    messages: &[Vec<u8>],
+    profile: QuoteProfile,
+) -> String {
+    let mut output = String::new();
+
+    for (i, message) in messages.iter().enumerate() {
+        let source = String::from_utf8_lossy(message);
+        let headers = Headers::parse(&source);
+        output.push_str(&engine.highlight_msgsep(i + 1, messages.len(), headers.subject()));
+        output.push('\n');
+        output.push_str(&render_message(engine, message, profile));
+    }
+    output
+}
+
+/// Decode, parse, and render a single message
+///
+/// Multipart, transfer-encoded, and non-UTF-8 messages are decoded first
+/// (see `mime::decode_parts`). The header block is rendered from the
+/// original message, followed by each decoded part on its own. Any other
+/// message is rendered as it is, with invalid UTF-8 replaced.
+pub fn render_message(
+    engine: &mut HighlighterEngine,
+    message: &[u8],
+    profile: QuoteProfile,
+) -> String {
+    let source = String::from_utf8_lossy(message);
+    let headers = Headers::parse(&source);
+    debug::print_headers(&headers);
+
+    let parts = match mime::decode_parts(message, &headers) {
+        Some(parts) if !parts.is_empty() => parts,
+        _ => return render_source(engine, &source, profile),
+    };
+    debug::print_parts(&parts);
+
+    let body_start = mime::body_start(message, &headers);
+    let multipart = mime::is_multipart(&headers);
+
+    let header_block = String::from_utf8_lossy(&message[..body_start]);
+    let mut output = render_source(engine, &header_block, profile);
+    for part in &parts {
+        if multipart {
+            /* The line break in front of a boundary belongs to the boundary */
+            if !output.ends_with('\n') {
+                output.push('\n');
+            }
+            output
+                .push_str(&engine.highlight_partsep(&part.content_type, part.filename.as_deref()));
+            output.push('\n');
+        }
+        output.push_str(&render_source(engine, &part.text, profile));
+    }
+    output
+}
+
+/// Parse and render a source that needs no further decoding
+///
+/// Quoted lines are recognized according to `profile`.
+fn render_source(engine: &mut HighlighterEngine, source: &str, profile: QuoteProfile) -> String {
+    let lines = Line::parse_lines(source, profile);
+    debug::print_lines(&lines);
+
+    let spans = span::build_spans(&lines);
+    debug::print_spans(&spans, engine.theme());
+
+    render(engine, source, &spans)
+}
+
+/// Render a parsed message into a highlighted string
+///
+/// Every span is sliced out of the original `source` and handed to the
+/// matching `HighlighterEngine` method. Everything in between the spans
+/// (line terminators, a trailing newline, etc.) is copied over verbatim,
+/// so the output is the input byte-for-byte plus the escape sequences.
+pub fn render(engine: &mut HighlighterEngine, source: &str, spans: &[Span]) -> String {
+    let mut output = String::with_capacity(source.len() * 2);
+    let mut cursor = 0;
+
+    for span in spans {
+        let start = span.get_start();
+        let end = span.get_end();
+
+        /* Glue (line terminators) between the previous span and this one */
+        output.push_str(&source[cursor..start]);
+
+        let text = &source[start..end];
+        let painted = match span {
+            Span::Headers { .. } => engine.highlight_headers(text),
+            Span::Text { .. } => engine.highlight_text(span),
+            Span::Separator { .. } => engine.highlight_sep(span),
+            Span::DiffStat { .. } => engine.highlight_stat(span),
+            Span::Signature { .. } => engine.highlight_sig(span),
+            Span::DiffHeader { .. } => engine.highlight_diffh(span),
+            Span::DiffMetadata { .. } => engine.highlight_diffm(span),
+            Span::HunkHeader { .. } => engine.highlight_hunkh(span),
+            Span::BinaryPatch { .. } => engine.highlight_binary(span),
+            Span::Code { .. } => engine.highlight_code(span),
+        };
+        output.push_str(&painted);
+
+        cursor = end;
+    }
+
+    output.push_str(&source[cursor..]);
+    output
+}
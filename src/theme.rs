@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use serde::de::{self, Deserializer, Visitor};
+use toml::{Table, Value};
+
+pub const RESET: &str = "\x1b[0m";
+
+/// Themes bundled with kmailight, by name
+const BUNDLED: &[(&str, &str)] = &[
+    ("dark", include_str!("../themes/dark.toml")),
+    ("light", include_str!("../themes/light.toml")),
+    ("solarized", include_str!("../themes/solarized.toml")),
+    (
+        "high-contrast",
+        include_str!("../themes/high-contrast.toml"),
+    ),
+];
+
+/// Name of the theme used unless another one is chosen
+pub const DEFAULT: &str = "dark";
+
+/// Names of the 16 colors of the terminal palette, in palette order
+const COLOR_NAMES: &[&str] = &[
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "bright-black",
+    "bright-red",
+    "bright-green",
+    "bright-yellow",
+    "bright-blue",
+    "bright-magenta",
+    "bright-cyan",
+    "bright-white",
+];
+
+/// A terminal color
+///
+/// In a theme, this is the name of one of the 16 palette colors (`red`,
+/// `bright-red`), a number of the 256 color palette (`208`), or a true
+/// color (`"#cb4b16"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// One of the 16 colors of the palette, which the terminal theme defines
+    Palette(u8),
+    /// One of the 256 colors of the extended palette
+    Fixed(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    /// Get the SGR parameters of the color, `base` is 30 for the foreground and 40 for the background
+    fn sgr(self, base: u8) -> String {
+        match self {
+            Color::Palette(index @ 0..8) => (base + index).to_string(),
+            Color::Palette(index) => (base + 60 + index - 8).to_string(),
+            Color::Fixed(index) => format!("{};5;{index}", base + 8),
+            Color::Rgb(r, g, b) => format!("{};2;{r};{g};{b}", base + 8),
+        }
+    }
+
+    /// Parse a color name, or a `#rrggbb` true color
+    fn from_name(name: &str) -> Option<Color> {
+        if let Some(hex) = name.strip_prefix('#') {
+            let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+            return match hex.len() {
+                6 => Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?)),
+                _ => None,
+            };
+        }
+        let index = COLOR_NAMES.iter().position(|&n| n == name)?;
+        Some(Color::Palette(index as u8))
+    }
+}
+
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ColorVisitor;
+
+        impl Visitor<'_> for ColorVisitor {
+            type Value = Color;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a color name, a number from 0 to 255, or \"#rrggbb\"")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<Color, E> {
+                Color::from_name(name)
+                    .ok_or_else(|| E::invalid_value(de::Unexpected::Str(name), &self))
+            }
+
+            fn visit_i64<E: de::Error>(self, index: i64) -> Result<Color, E> {
+                u8::try_from(index)
+                    .map(Color::Fixed)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(index), &self))
+            }
+        }
+
+        deserializer.deserialize_any(ColorVisitor)
+    }
+}
+
+/// How a piece of text is painted
+///
+/// In a theme, this is an inline table of the attributes that are set
+/// (`{ fg = "blue", bold = true }`), and `{}` for plain text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub dim: bool,
+}
+
+impl Style {
+    /// Get the ANSI escape sequence that turns the style on, empty for the plain style
+    pub fn escape(&self) -> String {
+        let mut params = Vec::new();
+        for (on, param) in [
+            (self.bold, "1"),
+            (self.dim, "2"),
+            (self.italic, "3"),
+            (self.underline, "4"),
+        ] {
+            if on {
+                params.push(param.to_string());
+            }
+        }
+        params.extend(self.fg.map(|color| color.sgr(30)));
+        params.extend(self.bg.map(|color| color.sgr(40)));
+
+        if params.is_empty() {
+            String::new()
+        } else {
+            format!("\x1b[{}m", params.join(";"))
+        }
+    }
+
+    /// Paint `text`, which is left as it is by the plain style, or if it is empty
+    pub fn paint(&self, text: &str) -> String {
+        match self.escape() {
+            escape if escape.is_empty() || text.is_empty() => text.to_string(),
+            escape => format!("{escape}{text}{RESET}"),
+        }
+    }
+
+    /// Get the style with only the background of this one
+    pub fn background(&self) -> Style {
+        Style {
+            bg: self.bg,
+            ..Style::default()
+        }
+    }
+}
+
+/// Styles of the lines of a diff
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DiffStyles {
+    /// The marker of added lines, their code only gets the background
+    pub add: Style,
+    /// The marker of removed lines, their code only gets the background
+    pub remove: Style,
+    /// The marker of context lines, their code only gets the background
+    pub context: Style,
+    pub hunk_header: Style,
+    /// The `diff --git` line
+    pub header: Style,
+}
+
+/// Styles of the extended header lines of a diff (see `MetadataKind`)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MetadataStyles {
+    pub index: Style,
+    /// The `---` and `+++` lines
+    pub file: Style,
+    pub new_file: Style,
+    pub deleted_file: Style,
+    pub mode: Style,
+    /// Renames, copies, and their similarity index
+    pub rename: Style,
+    pub binary: Style,
+}
+
+/// Styles of the header block of a message
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HeaderStyles {
+    /// Field names, with their colon
+    pub name: Style,
+    /// The tags at the start of the subject (`[PATCH v3 2/7]`)
+    pub subject_tags: Style,
+    /// The mbox `From ` line
+    pub envelope: Style,
+}
+
+/// Styles of the other parts of a message
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct MessageStyles {
+    /// The `---` and scissors lines
+    pub separator: Style,
+    pub signature: Style,
+    /// The line in front of each message of an mbox
+    pub mbox_separator: Style,
+    /// The line in front of each part of a multipart message
+    pub part_separator: Style,
+    /// The summary of a folded binary patch
+    pub binary_fold: Style,
+    /// The `\ No newline at end of file` marker, tinted like its line
+    pub no_newline: Style,
+    /// Lines damaged by a mail client
+    pub warning: Style,
+}
+
+/// The styles used to paint the output
+///
+/// A theme is a TOML document with one table per group of styles (see
+/// `themes/dark.toml`). A theme file can start from another theme with a
+/// `theme = "<name>"` key, the tables it has replace the styles they name.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Theme {
+    /// Styles of the quote marks by depth, deeper quotes start over
+    #[serde(default)]
+    quote: Vec<Style>,
+    /// Styles of the syntax captures, by capture name
+    #[serde(default)]
+    syntax: HashMap<String, Style>,
+    #[serde(default)]
+    pub diff: DiffStyles,
+    #[serde(default)]
+    pub metadata: MetadataStyles,
+    #[serde(default)]
+    pub headers: HeaderStyles,
+    #[serde(default)]
+    pub message: MessageStyles,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let table = bundled(DEFAULT).expect("the default theme is bundled");
+        Theme::deserialize(table).expect("bundled themes are valid")
+    }
+}
+
+impl Theme {
+    /// Get the style of a syntax capture, falling back to the style of its parents
+    ///
+    /// `function.macro.call` gets the first style defined among itself,
+    /// `function.macro`, and `function`.
+    pub fn syntax_style(&self, capture: &str) -> Option<&Style> {
+        let mut name = capture;
+        loop {
+            if let Some(style) = self.syntax.get(name) {
+                return Some(style);
+            }
+            name = &name[..name.rfind('.')?];
+        }
+    }
+
+    /// Get the style of the quote marks of the given depth (1 for `>`)
+    pub fn quote_style(&self, depth: usize) -> Style {
+        match self.quote.len() {
+            0 => Style::default(),
+            len => self.quote[depth.saturating_sub(1) % len],
+        }
+    }
+}
+
+/// Load the theme to paint the output with
+///
+/// The theme is `name` if given (the `--theme` option), or the one the
+/// `theme` key of `config.toml` in `config_dir` names, or the default one.
+/// It is either bundled (see `BUNDLED`), a `themes/<name>.toml` file of
+/// `config_dir`, or the path of a theme file. The styles of `config.toml`
+/// are applied on top of it.
+pub fn load(config_dir: Option<&Path>, name: Option<&str>) -> Result<Theme, String> {
+    let (base, config) = match config_dir {
+        Some(dir) => read_theme_file(&dir.join("config.toml"))?.unzip(),
+        None => (None, None),
+    };
+    let name = name
+        .map(str::to_string)
+        .or(base.flatten())
+        .unwrap_or_else(|| DEFAULT.to_string());
+
+    let mut styles = resolve(config_dir, &name, 0)?;
+    if let Some(config) = config {
+        merge(&mut styles, config);
+    }
+    Theme::deserialize(styles).map_err(|err| err.to_string())
+}
+
+/// Get the styles of the theme named `name`, with those of the themes it starts from
+fn resolve(config_dir: Option<&Path>, name: &str, depth: usize) -> Result<Table, String> {
+    /* Themes starting from each other in a loop would never end */
+    const MAX_DEPTH: usize = 8;
+
+    if let Some(styles) = bundled(name) {
+        return Ok(styles);
+    }
+    let path = if name.contains('/') || name.ends_with(".toml") {
+        PathBuf::from(name)
+    } else {
+        let Some(dir) = config_dir else {
+            return Err(unknown_theme(name));
+        };
+        dir.join("themes").join(format!("{name}.toml"))
+    };
+
+    let Some((base, over)) = read_theme_file(&path)? else {
+        return Err(unknown_theme(name));
+    };
+    if depth == MAX_DEPTH {
+        return Err(format!(
+            "{}: too many themes starting from each other",
+            path.display()
+        ));
+    }
+    let mut styles = resolve(config_dir, base.as_deref().unwrap_or(DEFAULT), depth + 1)?;
+    merge(&mut styles, over);
+    Ok(styles)
+}
+
+/// Get the styles of a bundled theme
+fn bundled(name: &str) -> Option<Table> {
+    let (_, text) = BUNDLED.iter().find(|&&(n, _)| n == name)?;
+    Some(toml::from_str(text).expect("bundled themes are valid TOML"))
+}
+
+fn unknown_theme(name: &str) -> String {
+    let names: Vec<_> = BUNDLED.iter().map(|&(n, _)| n).collect();
+    format!(
+        "unknown theme '{name}' (expected one of: {}, or a theme file)",
+        names.join(", ")
+    )
+}
+
+/// Read a theme file (or `config.toml`), if there is one
+///
+/// Returns the name of the theme it starts from, and its styles, which are
+/// checked on their own so that errors point to the right file.
+fn read_theme_file(path: &Path) -> Result<Option<(Option<String>, Table)>, String> {
+    let error = |err: &dyn fmt::Display| format!("{}: {err}", path.display());
+
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(error(&err)),
+    };
+    let mut styles: Table = toml::from_str(&text).map_err(|err| error(&err))?;
+
+    let base = match styles.remove("theme") {
+        None => None,
+        Some(Value::String(name)) => Some(name),
+        Some(_) => return Err(error(&"`theme` must be the name of a theme")),
+    };
+    Theme::deserialize(styles.clone()).map_err(|err| error(&err))?;
+    Ok(Some((base, styles)))
+}
+
+/// Apply the styles of `over` on top of `styles`
+///
+/// The styles of a table replace the ones of the same name, the others are
+/// kept. The quote styles are replaced as a whole.
+fn merge(styles: &mut Table, over: Table) {
+    for (key, value) in over {
+        match (styles.get_mut(&key), value) {
+            (Some(Value::Table(group)), Value::Table(over)) => group.extend(over),
+            (_, value) => {
+                styles.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a theme from its TOML text, without starting from another theme
+    fn theme(text: &str) -> Result<Theme, toml::de::Error> {
+        Theme::deserialize(toml::from_str::<Table>(text).unwrap())
+    }
+
+    /// Create an empty directory for a test under the temporary directory
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kmailight-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("themes")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn color_names() {
+        assert_eq!(Color::from_name("red"), Some(Color::Palette(1)));
+        assert_eq!(Color::from_name("bright-red"), Some(Color::Palette(9)));
+        assert_eq!(
+            Color::from_name("#cb4b16"),
+            Some(Color::Rgb(0xcb, 0x4b, 0x16))
+        );
+        assert_eq!(Color::from_name("#cb4b1"), None);
+        assert_eq!(Color::from_name("#cb4b1g"), None);
+        assert_eq!(Color::from_name("orange"), None);
+    }
+
+    #[test]
+    fn unknown_color_in_a_theme() {
+        assert!(theme("[diff]\nadd = { fg = \"orange\" }\n").is_err());
+        assert!(theme("[diff]\nadd = { fg = 256 }\n").is_err());
+        assert!(theme("[diff]\nadd = { fg = 208 }\n").is_ok());
+    }
+
+    #[test]
+    fn escape_sequences() {
+        let style = Style {
+            fg: Some(Color::Palette(9)),
+            bg: Some(Color::Fixed(208)),
+            bold: true,
+            ..Style::default()
+        };
+        assert_eq!(style.escape(), "\x1b[1;91;48;5;208m");
+        assert_eq!(Style::default().paint("text"), "text");
+    }
+
+    #[test]
+    fn syntax_styles_fall_back_to_their_parents() {
+        let theme = theme(
+            "[syntax]\n\
+             function = { fg = \"blue\" }\n\
+             \"function.macro\" = { fg = \"magenta\" }\n",
+        )
+        .unwrap();
+        let fg = |capture| theme.syntax_style(capture).and_then(|style| style.fg);
+        assert_eq!(fg("function.builtin"), Some(Color::Palette(4)));
+        assert_eq!(fg("function.macro.call"), Some(Color::Palette(5)));
+        assert_eq!(fg("keyword"), None);
+    }
+
+    #[test]
+    fn user_theme_over_a_bundled_one() {
+        let config_dir = test_dir("theme");
+        fs::write(
+            config_dir.join("themes/mine.toml"),
+            "theme = \"light\"\n[diff]\nadd = { fg = \"green\", bold = true }\n",
+        )
+        .unwrap();
+        fs::write(
+            config_dir.join("config.toml"),
+            "theme = \"mine\"\n[message]\nwarning = { fg = \"red\" }\n",
+        )
+        .unwrap();
+
+        let light = load(None, Some("light")).unwrap();
+        let mine = load(Some(&config_dir), None).unwrap();
+        let add = Style {
+            fg: Some(Color::Palette(2)),
+            bold: true,
+            ..Style::default()
+        };
+        assert_eq!(mine.diff.add, add);
+        assert_eq!(mine.diff.remove, light.diff.remove);
+        assert_eq!(mine.message.warning.fg, Some(Color::Palette(1)));
+        assert_eq!(mine.message.warning.bg, None);
+        assert_eq!(mine.message.signature, light.message.signature);
+
+        fs::remove_dir_all(&config_dir).unwrap();
+    }
+
+    #[test]
+    fn themes_starting_from_each_other() {
+        let config_dir = test_dir("theme-loop");
+        fs::write(config_dir.join("themes/a.toml"), "theme = \"b\"\n").unwrap();
+        fs::write(config_dir.join("themes/b.toml"), "theme = \"a\"\n").unwrap();
+
+        let err = load(Some(&config_dir), Some("a")).unwrap_err();
+        assert!(err.contains("too many themes"), "{err}");
+        let err = load(Some(&config_dir), Some("c")).unwrap_err();
+        assert!(err.starts_with("unknown theme 'c'"), "{err}");
+
+        fs::remove_dir_all(&config_dir).unwrap();
+    }
+}